@@ -1,6 +1,6 @@
 import { LitElement, css, html } from "lit";
 import { customElement, property } from "lit/decorators.js"
-import { DecodedPacket, SensorPacket, ConfigPacket, GPSPacket, SatellitePacket, parse_packet, KalmanVoltagePacket } from "../packet";
+import { DecodedPacket, SensorPacket, ConfigPacket, GPSPacket, SatellitePacket, parse_packet, KalmanVoltagePacket, ReceivedPacket, SourceEvent, GainControl, GainSettings, DecoderStatus } from "../packet";
 
 
 @customElement('sensor-packet')
@@ -277,6 +277,9 @@ export class TeleMegaDataView extends LitElement {
     @property()
     public sat: SatellitePacketView = new SatellitePacketView();
 
+    @property()
+    public last: ReceivedPacket | null = null;
+
     static styles = css`
     .grid-container {
         display: grid;
@@ -289,8 +292,13 @@ export class TeleMegaDataView extends LitElement {
     }
     `
     render() {
+        const last = this.last;
         return html`
     <div>
+      ${last == null ? html`No packets` : html`
+        ${last.label ?? last.frequency}${last.source == null ? "" : ` via ${last.source}`} at ${new Date(last.received_at).toLocaleTimeString()}:
+        ${last.rssi.toFixed(1)} dBFS, SNR ${last.snr.toFixed(1)} dB, offset ${last.freq_error.toFixed(0)} Hz
+      `}
       <div class="grid-container">
         <div class="grid-item">Sensor: ${this.sensor}</div>
         <div class="grid-item">Config: ${this.config}</div>
@@ -303,6 +311,145 @@ export class TeleMegaDataView extends LitElement {
 }
 
 
+@customElement('source-status')
+export class SourceStatusView extends LitElement {
+    @property()
+    public last: SourceEvent | null = null;
+
+    @property()
+    public gain: GainSettings | null = null;
+
+    @property()
+    public overrun: SourceEvent & { type: "Overrun" } | null = null;
+
+    static styles = css`
+    .lost {
+        color: white;
+        background: red;
+        padding: 3px;
+    }
+    `
+    render() {
+        const overrun = this.overrun == null ? html`` : html`
+    <div>
+      Samples dropped at ${new Date(this.overrun.at).toLocaleTimeString()},
+      ${this.overrun.total_lost.toLocaleString()} samples lost in all
+    </div>
+        `;
+        const gain = this.gain == null ? overrun : html`
+    <div>Gain: LNA ${this.gain.lna} dB, VGA ${this.gain.vga} dB, amp ${this.gain.amp ? "on" : "off"}</div>
+    ${overrun}
+        `;
+        const last = this.last;
+        if(last == null || last.type == "GainChanged" || last.type == "Overrun" || last.type == "AntennaPower") return gain;
+        const at = new Date(last.at).toLocaleTimeString();
+        const radio = last.source ?? "Radio";
+        if(last.type == "Disconnected"){
+            return html`<div class="lost">${radio} disconnected at ${at}, waiting for it to come back</div>${gain}`;
+        }
+        return html`
+    <div>
+      ${radio} reconnected at ${at} after ${(last.dropout_ms / 1000).toFixed(1)} s,
+      ${last.samples_lost.toLocaleString()} samples lost
+    </div>
+    ${gain}
+        `
+    }
+}
+
+
+@customElement('gain-control')
+export class GainControlView extends LitElement {
+    @property()
+    public mode: GainControl["mode"] = "peak";
+
+    @property()
+    public error: string | null = null;
+
+    render() {
+        const fields = {
+            manual: html`
+                LNA <input name="lna" type="number" min="0" max="40" step="8" value="16"> dB
+                VGA <input name="vga" type="number" min="0" max="62" step="2" value="20"> dB`,
+            peak: html`
+                Low <input name="low" type="number" value="40">
+                High <input name="high" type="number" value="90">
+                Window <input name="window_ms" type="number" value="1000"> ms`,
+            rms: html`
+                Target <input name="target_dbfs" type="number" value="-25"> dBFS
+                ± <input name="hysteresis_db" type="number" value="3"> dB
+                Window <input name="window_ms" type="number" value="1000"> ms`,
+        };
+        return html`
+    <form @submit=${this.submit}>
+      Gain
+      <select name="mode" @change=${(e: Event) => this.mode = (e.target as HTMLSelectElement).value as GainControl["mode"]}>
+        <option value="peak" ?selected=${this.mode == "peak"}>Peak AGC</option>
+        <option value="rms" ?selected=${this.mode == "rms"}>RMS AGC</option>
+        <option value="manual" ?selected=${this.mode == "manual"}>Manual</option>
+      </select>
+      ${fields[this.mode]}
+      <label><input name="amp" type="checkbox" checked> Amp</label>
+      <button type="submit">Set</button>
+      ${this.error ?? ""}
+    </form>
+        `
+    }
+
+    async submit(e: SubmitEvent) {
+        e.preventDefault();
+        const form = new FormData(e.target as HTMLFormElement);
+        const control: Record<string, string | number | boolean> = { mode: this.mode, amp: form.get("amp") != null };
+        for(const [name, value] of form){
+            if(name != "mode" && name != "amp") control[name] = Number(value);
+        }
+        const response = await fetch("/gain", { method: "POST", body: JSON.stringify(control) });
+        this.error = response.ok ? null : await response.text();
+    }
+}
+
+@customElement('antenna-power')
+export class AntennaPowerView extends LitElement {
+    @property()
+    public on = false;
+
+    @property()
+    public allowed = false;
+
+    @property()
+    public error: string | null = null;
+
+    static styles = css`
+    .on {
+        color: white;
+        background: orange;
+        font-weight: bold;
+        padding: 3px;
+    }
+    `
+    render() {
+        // only offered when the server was started with --antenna-power
+        const toggle = this.allowed || this.on
+            ? html`<button @click=${() => this.set(!this.on)}>Switch ${this.on ? "off" : "on"}</button>`
+            : html``;
+        if(this.on){
+            return html`<div class="on">Antenna power ON: 3.3 V on the antenna cable ${toggle} ${this.error ?? ""}</div>`;
+        }
+        return html`<div>Antenna power off ${toggle} ${this.error ?? ""}</div>`;
+    }
+
+    async set(on: boolean) {
+        const response = await fetch("/antenna", { method: "POST", body: on ? "on" : "off" });
+        this.error = response.ok ? null : await response.text();
+    }
+}
+
+const sourceStatus = new SourceStatusView();
+document.body.appendChild(sourceStatus);
+const antennaPower = new AntennaPowerView();
+document.body.appendChild(antennaPower);
+document.body.appendChild(new GainControlView());
+
 const view = new TeleMegaDataView();
 const view2 = new TeleMegaDataView();
 const view3 = new TeleMegaDataView();
@@ -358,15 +505,42 @@ export class FourDataView extends LitElement {
 const fullDataView = new FourDataView();
 document.body.appendChild(fullDataView);
 
+setInterval(async ()=>{
+    const events: SourceEvent[] = await (await fetch("/events")).json();
+    if(events instanceof Array && events.length > 0){
+        for(const event of events){
+            // the AGC changes gain often, so keep it apart from dropouts
+            if(event.type == "GainChanged"){
+                if(event.source == null) sourceStatus.gain = event.gain;
+            } else if(event.type == "Overrun"){
+                sourceStatus.overrun = event;
+            } else if(event.type == "AntennaPower"){
+                if(event.source == null) antennaPower.on = event.on;
+            } else {
+                sourceStatus.last = event;
+            }
+        }
+        sourceStatus.requestUpdate();
+    }
+}, 1000);
+
+setInterval(async ()=>{
+    const status: DecoderStatus = await (await fetch("/status")).json();
+    const main = status.receivers.find(receiver => receiver.source == null);
+    antennaPower.on = main?.antenna_power ?? false;
+    antennaPower.allowed = main?.antenna_power_allowed ?? false;
+}, 2000);
+
 let in_flight = false;
 setInterval(async ()=>{
     if(in_flight) return;
     in_flight = true;
-    const json: DecodedPacket[] = await (await fetch("/getdata")).json()
+    const json: ReceivedPacket[] = await (await fetch("/getdata")).json()
     in_flight = false;
 
     if(json instanceof Array){
-        for(const elem of json){
+        for(const received of json){
+            const elem = received.packet;
             let v = view4;
             if(elem.serial == 11216){
                 v = view;
@@ -378,6 +552,8 @@ setInterval(async ()=>{
             if(elem.serial == 11069){
                 v = view3
             }
+            v.last = received;
+            v.requestUpdate();
             console.log(elem)
             switch(elem.ptype){
                 case 1:
@@ -7,6 +7,93 @@ export type GFSKPacket = {
 
 export type DecodedPacket = SensorPacket | ConfigPacket | GPSPacket | SatellitePacket | KalmanVoltagePacket | UnknownPacket;
 
+export type ReceivedPacket = {
+    received_at: number;
+    sample_index: number;
+    source: string | null;
+    frequency: number;
+    label: string | null;
+    rssi: number;
+    snr: number;
+    freq_error: number;
+    packet: DecodedPacket;
+}
+
+export type SourceEvent = {
+    source: string | null;
+} & ({
+    type: "Disconnected";
+    at: number;
+} | {
+    type: "Reconnected";
+    at: number;
+    dropout_ms: number;
+    samples_lost: number;
+} | {
+    type: "GainChanged";
+    at: number;
+    gain: GainSettings;
+} | {
+    type: "AntennaPower";
+    at: number;
+    on: boolean;
+} | {
+    type: "Overrun";
+    at: number;
+    samples_lost: number;
+    total_lost: number;
+})
+
+export type ReceiverDiagnostics = {
+    source: string | null;
+    board: {
+        board: string;
+        board_id: number | null;
+        firmware: string | null;
+        serial: string | null;
+        api_version: string;
+        cpld_checksum: number | null;
+    };
+    connected: boolean;
+    sample_rate: number;
+    sample_clock_hz: number;
+    sample_divider: number;
+    center: number;
+    gain: GainSettings;
+    antenna_power: boolean;
+    antenna_power_allowed: boolean;
+}
+
+export type DecoderStatus = {
+    running: boolean;
+    receivers: ReceiverDiagnostics[];
+}
+
+export type GainSettings = {
+    lna: number;
+    vga: number;
+    amp: boolean;
+}
+
+export type GainControl = {
+    mode: "manual";
+    lna: number;
+    vga: number;
+    amp: boolean;
+} | {
+    mode: "peak";
+    low: number;
+    high: number;
+    window_ms: number;
+    amp: boolean;
+} | {
+    mode: "rms";
+    target_dbfs: number;
+    hysteresis_db: number;
+    window_ms: number;
+    amp: boolean;
+}
+
 export type KalmanVoltagePacket = {
     serial: number;
     tick: number;
@@ -0,0 +1,40 @@
+//! Receive for a few seconds using the async stream API and print the sample rate achieved.
+//!
+//! `cargo run -p async-libhackrf --example rx_stream -- [transfers] [transfer size]`
+
+use std::time::{Duration, Instant};
+use async_libhackrf::{HackRfOne, RxConfig};
+use futures_lite::future::block_on;
+use futures_lite::StreamExt;
+
+fn main() -> Result<(), async_libhackrf::Error> {
+    let mut args = std::env::args().skip(1);
+    let mut config = RxConfig::default();
+    if let Some(transfers) = args.next() {
+        config.transfers = transfers.parse().expect("transfers should be a number");
+    }
+    if let Some(size) = args.next() {
+        config.transfer_size = size.parse().expect("transfer size should be a number");
+    }
+
+    let mut radio = HackRfOne::new().expect("no HackRF found");
+    radio.set_sample_rate(20_000_000, 1)?;
+    radio.set_freq(436_850_000)?;
+    let mut radio = radio.into_rx_stream(config)?;
+
+    block_on(async {
+        let start = Instant::now();
+        let mut samples = 0;
+        while start.elapsed() < Duration::from_secs(5) {
+            match radio.next().await {
+                Some(buffer) => samples += buffer?.len() / 2,
+                None => break,
+            }
+        }
+        println!("{:.2} MS/s with {config:?}", samples as f64 / start.elapsed().as_secs_f64() / 1e6);
+        Ok::<(), async_libhackrf::Error>(())
+    })?;
+
+    radio.stop_rx()?;
+    Ok(())
+}
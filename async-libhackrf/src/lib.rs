@@ -4,11 +4,21 @@
 #![warn(missing_docs)]
 
 use std::fmt::{Debug, Formatter};
-use std::sync::mpsc;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{ready, Context, Poll};
 use std::thread;
-use std::time::Duration;
-use nusb::transfer::{Completion, Control, ControlType, Direction, Recipient, RequestBuffer};
+use std::time::{Duration, Instant};
+use nusb::transfer::{Direction, TransferError};
 use futures_lite::future::{block_on, FutureExt};
+use futures_lite::Stream;
+
+mod transport;
+mod simulated;
+
+pub use crate::transport::{BulkIn, BulkOut, Transport};
+pub use crate::simulated::{ControlRequest, SimulatedSettings, SimulatedTransport};
+use crate::transport::{next_in, next_out, UsbTransport};
 
 
 /// HackRF USB vendor ID.
@@ -16,9 +26,12 @@ const HACKRF_USB_VID: u16 = 0x1D50;
 /// HackRF One USB product ID.
 const HACKRF_ONE_USB_PID: u16 = 0x6089;
 
-#[allow(dead_code)]
+/// Vendor requests understood by the HackRF firmware, as in libhackrf's `hackrf_vendor_request`.
+/// Mostly of use with [`SimulatedTransport::stall`].
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
-enum Request {
+pub enum Request {
     SetTransceiverMode = 1,
     Max2837Write = 2,
     Max2837Read = 3,
@@ -110,6 +123,12 @@ pub enum Error {
     },
     /// A provided argument was out of range.
     Argument,
+    /// No HackRF One with the serial number asked for could be opened, either because none is
+    /// attached or because another program is using it.
+    NotFound,
+    /// More than one HackRF One matches a shortened serial number. Holds their full serial
+    /// numbers.
+    AmbiguousSerial(Vec<String>),
 }
 
 // impl From<nusb::Error> for Error {
@@ -126,7 +145,10 @@ impl From<nusb::transfer::TransferError> for Error {
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::AmbiguousSerial(serials) => write!(f, "more than one HackRF matches: {}", serials.join(", ")),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -145,6 +167,12 @@ impl Version {
     pub fn sub_minor(&self) -> u8 { self.2 }
 }
 
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
 impl From<u16> for Version {
     fn from(value: u16) -> Self {
         Version(((value & 0xFF00) >> 8) as u8, ((value & 0x00F0) >> 4) as u8, (value & 0x000F) as u8)
@@ -152,7 +180,37 @@ impl From<u16> for Version {
 }
 
 /// Typestate for RX mode.
-pub struct RxMode(mpsc::Receiver<Completion<Vec<u8>>>, async_channel::Sender<()>, thread::JoinHandle<()>);
+pub struct RxMode(
+    mpsc::Receiver<Result<RxTransfer, TransferError>>,
+    async_channel::Sender<()>,
+    thread::JoinHandle<()>,
+    Arc<Mutex<RxStats>>,
+);
+
+/// Samples from one transfer, see [`HackRfOne::rx_transfer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RxTransfer {
+    /// Pairs of signed 8-bit IQ, as from [`HackRfOne::rx`].
+    pub data: Vec<u8>,
+    /// Transfers thrown away since the previous one was read because the reader had fallen
+    /// behind. If this is not 0 the samples do not follow on from the previous ones.
+    pub skipped: u64,
+}
+
+/// How well receiving has kept up, see [`HackRfOne::stats`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RxStats {
+    /// Transfers completed successfully.
+    pub transfers: u64,
+    /// Transfers that completed with an error.
+    pub failed: u64,
+    /// Transfers thrown away because [`RxConfig::backlog`] transfers were already waiting to be
+    /// read.
+    pub dropped: u64,
+    /// Longest time between two transfers completing. Once this is longer than all the queued
+    /// transfers take to fill, the radio will have run out of buffers and lost samples.
+    pub longest_gap: Duration,
+}
 
 impl Debug for RxMode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -161,22 +219,126 @@ impl Debug for RxMode {
     }
 }
 
+/// Typestate for RX mode read as an async [`Stream`], see [`HackRfOne::into_rx_stream`].
+pub struct RxStream {
+    queue: Box<dyn BulkIn>,
+    transfer_size: usize,
+    disconnected: bool,
+}
+
+impl Debug for RxStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RxStream")
+            .field("transfer_size", &self.transfer_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Typestate for TX mode, see [`HackRfOne::into_tx_mode`].
+pub struct TxMode {
+    queue: Box<dyn BulkOut>,
+    config: RxConfig,
+    // samples waiting to make up a full transfer
+    pending: Vec<u8>,
+}
+
+impl Debug for TxMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxMode")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Typestate for sweep mode, see [`HackRfOne::into_sweep_mode`].
+pub struct SweepMode {
+    queue: Box<dyn BulkIn>,
+    transfer_size: usize,
+}
+
+impl Debug for SweepMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SweepMode")
+            .field("transfer_size", &self.transfer_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bytes the radio records at each frequency of a sweep, starting with a header giving the
+/// frequency.
+pub const SWEEP_BLOCK_SIZE: usize = 16384;
+/// Size of the header at the start of each sweep block.
+const SWEEP_HEADER_SIZE: usize = 10;
+const MAX_SWEEP_RANGES: usize = 10;
+
+/// Order the frequencies of a sweep are visited in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SweepStyle {
+    /// Step by the step width.
+    Linear = 0,
+    /// Alternate between the step and a quarter of a step further on, as `hackrf_sweep` does so
+    /// that the edges and center of each capture can be thrown away.
+    Interleaved = 1,
+}
+
+/// Where and how to sweep, see [`HackRfOne::into_sweep_mode`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SweepConfig {
+    /// Start and stop frequencies in MHz of up to 10 ranges, each a whole number of steps wide.
+    pub ranges: Vec<(u16, u16)>,
+    /// Distance between tuned frequencies in Hz.
+    pub step_width: u32,
+    /// How far above the reported frequency the radio is actually tuned, in Hz.
+    pub offset: u32,
+    /// Order the frequencies are visited in.
+    pub style: SweepStyle,
+}
+
+/// Samples recorded at one frequency of a sweep.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SweepBlock {
+    /// Frequency in Hz, which the radio was tuned `offset` above.
+    pub frequency: u64,
+    /// Pairs of signed 8-bit IQ, as from [`HackRfOne::rx`].
+    pub samples: Vec<u8>,
+}
+
+/// USB transfer settings for receiving, also used for transmitting.
+///
+/// More or larger transfers ride out longer pauses in whatever is reading (or producing) the
+/// samples, at the cost of latency and memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RxConfig {
+    /// Number of transfers kept queued with the USB controller.
+    pub transfers: usize,
+    /// Size of each transfer in bytes, which must be a multiple of 512.
+    pub transfer_size: usize,
+    /// Number of completed transfers held for a reader that has fallen behind before newer ones
+    /// are thrown away, at least 1. Only used by [`HackRfOne::into_rx_mode_with`].
+    pub backlog: usize,
+}
+
+impl Default for RxConfig {
+    fn default() -> Self {
+        RxConfig { transfers: 4, transfer_size: 262144, backlog: 64 }
+    }
+}
+
 /// Typestate for an unknown mode.
 #[derive(Debug)]
 pub struct UnknownMode;
 
 /// HackRF One software defined radio.
 pub struct HackRfOne<MODE> {
-    dh: nusb::Device,
-    interface: nusb::Interface,
-    desc: nusb::DeviceInfo,
+    transport: Box<dyn Transport>,
     #[allow(dead_code)]
     mode: MODE,
     to: Duration,
 }
 
 impl HackRfOne<UnknownMode> {
-    /// Open a new HackRF One.
+    /// Open the first HackRF One that is not already in use.
     ///
     /// # Example
     ///
@@ -186,26 +348,170 @@ impl HackRfOne<UnknownMode> {
     /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
     /// ```
     pub fn new() -> Option<HackRfOne<UnknownMode>> {
-        let devices = nusb::list_devices().ok()?;
-
-        for device in devices {
-            if device.vendor_id() == HACKRF_USB_VID && device.product_id() == HACKRF_ONE_USB_PID {
-                match device.open() {
-                    Ok(handle) => {
-                        return Some(HackRfOne {
-                            interface: handle.claim_interface(0).expect("Could not claim interface 0???"),
-                            dh: handle,
-                            desc: device,
-                            mode: UnknownMode,
-                            to: Duration::from_secs(1),
-                        })
-                    }
-                    Err(_) => continue,
-                }
+        nusb::list_devices().ok()?
+            .filter(is_hackrf_one)
+            .find_map(HackRfOne::open)
+    }
+
+    /// Open the HackRF One with the given serial number, for when more than one is attached.
+    ///
+    /// As with `hackrf_transfer -d`, the serial may be shortened to its last few digits and
+    /// leading zeros may be left off. A serial that is all zeros is rejected with
+    /// [`Error::Argument`], and one that is short enough to match more than one radio with
+    /// [`Error::AmbiguousSerial`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::open_by_serial("457863dc2b3a7e1b")?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn open_by_serial(serial: &str) -> Result<HackRfOne<UnknownMode>, Error> {
+        let wanted = serial.trim_start_matches('0').to_ascii_lowercase();
+        // nothing left would match every radio
+        if wanted.is_empty() {
+            return Err(Error::Argument);
+        }
+        let matches = |serial: &str| serial.to_ascii_lowercase().ends_with(&wanted);
+        let devices = nusb::list_devices().map_err(|_| Error::NotFound)?;
+
+        let mut found = Vec::new();
+        for device in devices.filter(is_hackrf_one) {
+            // avoid opening radios other programs are using where the descriptor says which is which
+            let descriptor_serial = device.serial_number().map(str::to_ascii_lowercase);
+            if descriptor_serial.as_deref().is_some_and(|serial| !matches(serial)) {
+                continue;
             }
+            match HackRfOne::open(device) {
+                Some(radio) => match radio.serial_number() {
+                    Ok(serial) if matches(&serial) => found.push((serial, Some(radio))),
+                    _ => {}
+                },
+                // in use, but still makes a shortened serial ambiguous
+                None => found.extend(descriptor_serial.map(|serial| (serial, None))),
+            }
+        }
+        match found.len() {
+            0 => Err(Error::NotFound),
+            1 => found.pop().and_then(|(_, radio)| radio).ok_or(Error::NotFound),
+            _ => Err(Error::AmbiguousSerial(found.into_iter().map(|(serial, _)| serial).collect())),
+        }
+    }
+
+    fn open(device: nusb::DeviceInfo) -> Option<HackRfOne<UnknownMode>> {
+        // fails if another program is using the radio
+        let transport = UsbTransport::open(device)?;
+        Some(HackRfOne::with_transport(transport))
+    }
+
+    /// Talk to a radio over something other than USB, such as a [`SimulatedTransport`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use async_libhackrf::{HackRfOne, SimulatedTransport, UnknownMode};
+    ///
+    /// let radio: HackRfOne<UnknownMode> = HackRfOne::with_transport(SimulatedTransport::new());
+    /// assert_eq!(radio.board_id()?, 2);
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn with_transport(transport: impl Transport + 'static) -> HackRfOne<UnknownMode> {
+        HackRfOne {
+            transport: Box::new(transport),
+            mode: UnknownMode,
+            to: Duration::from_secs(1),
         }
+    }
+}
+
+fn is_hackrf_one(device: &nusb::DeviceInfo) -> bool {
+    device.vendor_id() == HACKRF_USB_VID && device.product_id() == HACKRF_ONE_USB_PID
+}
 
-        None
+/// An attached HackRF One, as found by [`list_devices`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Serial number as 32 hex digits, as printed by `hackrf_info`.
+    pub serial: String,
+    /// Board ID, see [`HackRfOne::board_id`]. `None` if the radio is in use by another program.
+    pub board_id: Option<u8>,
+    /// Firmware version string. `None` if the radio is in use by another program.
+    pub firmware: Option<String>,
+    /// USB device version.
+    pub device_version: Version,
+}
+
+/// List the attached HackRF Ones.
+///
+/// Radios that are in use elsewhere cannot be opened, so only what their USB descriptor says
+/// about them is listed.
+///
+/// # Example
+///
+/// ```no_run
+/// for device in async_libhackrf::list_devices() {
+///     println!("{} firmware {:?}", device.serial, device.firmware);
+/// }
+/// ```
+pub fn list_devices() -> Vec<DeviceInfo> {
+    let Ok(devices) = nusb::list_devices() else {
+        return Vec::new();
+    };
+    devices.filter(is_hackrf_one)
+        .map(|device| {
+            let device_version = device.device_version().into();
+            let descriptor_serial = device.serial_number().unwrap_or_default().to_ascii_lowercase();
+            match HackRfOne::open(device) {
+                Some(radio) => DeviceInfo {
+                    serial: radio.serial_number().unwrap_or(descriptor_serial),
+                    board_id: radio.board_id().ok(),
+                    firmware: radio.version().ok(),
+                    device_version,
+                },
+                None => DeviceInfo {
+                    serial: descriptor_serial,
+                    board_id: None,
+                    firmware: None,
+                    device_version,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Name of a board ID from [`HackRfOne::board_id`], as printed by `hackrf_info`.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(async_libhackrf::board_name(2), "HackRF One");
+/// ```
+pub fn board_name(board_id: u8) -> &'static str {
+    match board_id {
+        0 => "Jellybean",
+        1 => "Jawbreaker",
+        2 | 4 => "HackRF One",
+        3 => "rad1o",
+        0xFF => "undetected",
+        _ => "unrecognized",
+    }
+}
+
+/// Part ID and serial number burned into the HackRF's microcontroller.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PartIdSerialNo {
+    /// LPC43xx part ID.
+    pub part_id: [u32; 2],
+    /// Serial number words, most significant first.
+    pub serial_no: [u32; 4],
+}
+
+impl PartIdSerialNo {
+    /// The serial number as 32 lowercase hex digits, as printed by `hackrf_info`.
+    pub fn serial(&self) -> String {
+        self.serial_no.iter().map(|word| format!("{word:08x}")).collect()
     }
 }
 
@@ -218,12 +524,7 @@ impl<MODE> HackRfOne<MODE> {
     ) -> Result<[u8; N], Error> {
         let mut buf: [u8; N] = [0; N];
 
-        let n: usize = self.interface.control_in_blocking(Control {
-            control_type: ControlType::Vendor,
-            recipient: Recipient::Device,
-            request: request.into(),
-            value, index
-        }, &mut buf, self.to)?;
+        let n: usize = self.transport.control_in(request.into(), value, index, &mut buf, self.to)?;
         if n != buf.len() {
             Err(Error::CtrlTransfer {
                 dir: Direction::In,
@@ -242,12 +543,7 @@ impl<MODE> HackRfOne<MODE> {
         index: u16,
         buf: &[u8],
     ) -> Result<(), Error> {
-        let n: usize = self.interface.control_out_blocking(Control {
-            control_type: ControlType::Vendor,
-            recipient: Recipient::Device,
-            request: request.into(),
-            value, index
-        }, buf, self.to)?;
+        let n: usize = self.transport.control_out(request.into(), value, index, buf, self.to)?;
         if n != buf.len() {
             Err(Error::CtrlTransfer {
                 dir: Direction::Out,
@@ -289,7 +585,7 @@ impl<MODE> HackRfOne<MODE> {
     /// assert_eq!(radio.device_version(), Version(1, 0, 4));
     /// ```
     pub fn device_version(&self) -> Version {
-        self.desc.device_version().into()
+        self.transport.device_version().into()
     }
 
     /// Set the timeout for USB transfers.
@@ -324,6 +620,52 @@ impl<MODE> HackRfOne<MODE> {
         Ok(data[0])
     }
 
+    /// Read the part ID and serial number.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// println!("Serial number: {}", radio.board_partid_serialno()?.serial());
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn board_partid_serialno(&self) -> Result<PartIdSerialNo, Error> {
+        let data: [u8; 24] = self.read_control(Request::BoardPartidSerialnoRead, 0, 0)?;
+        let word = |i: usize| u32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]);
+        Ok(PartIdSerialNo {
+            part_id: [word(0), word(1)],
+            serial_no: [word(2), word(3), word(4), word(5)],
+        })
+    }
+
+    /// Read the serial number as 32 lowercase hex digits.
+    pub fn serial_number(&self) -> Result<String, Error> {
+        Ok(self.board_partid_serialno()?.serial())
+    }
+
+    /// Read the checksum of the CPLD's configuration, to check the CPLD was programmed with the
+    /// bitstream that goes with the firmware.
+    ///
+    /// `hackrf_info` does not read this until HackRF issue #609 is fixed, so only read it when
+    /// asked to and while the radio is idle.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// println!("CPLD checksum: 0x{:08x}", radio.cpld_checksum()?);
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn cpld_checksum(&self) -> Result<u32, Error> {
+        self.check_api_version(Version::from_bcd(0x0103))?;
+        let data: [u8; 4] = self.read_control(Request::CpldChecksum, 0, 0)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
     /// Read the firmware version.
     ///
     /// # Example
@@ -336,17 +678,14 @@ impl<MODE> HackRfOne<MODE> {
     /// ```
     pub fn version(&self) -> Result<String, Error> {
         let mut buf: [u8; 16] = [0; 16];
-        let n: usize = self.interface.control_in_blocking(Control {
-            control_type: ControlType::Vendor,
-            recipient: Recipient::Device,
-            request: Request::VersionStringRead.into(),
-            value: 0, index: 0
-        }, &mut buf, self.to)?;
+        let n: usize = self.transport.control_in(Request::VersionStringRead.into(), 0, 0, &mut buf, self.to)?;
         Ok(String::from_utf8_lossy(&buf[0..n]).into())
     }
 
     /// Set the center frequency.
     ///
+    /// This can be called while receiving to retune without stopping the stream.
+    ///
     /// # Example
     ///
     /// Set the frequency to 915MHz.
@@ -367,6 +706,8 @@ impl<MODE> HackRfOne<MODE> {
     /// In GNU radio this is used as the RF gain, where a value of 0 dB is off,
     /// and a value of 14 dB is on.
     ///
+    /// This can be called while receiving.
+    ///
     /// # Example
     ///
     /// Disable the amplifier.
@@ -456,6 +797,8 @@ impl<MODE> HackRfOne<MODE> {
     ///
     /// This is also known as the IF gain.
     ///
+    /// This can be called while receiving, e.g. for automatic gain control.
+    ///
     /// # Example
     ///
     /// Set the LNA gain to 16 dB (generally a reasonable gain to start with).
@@ -485,6 +828,8 @@ impl<MODE> HackRfOne<MODE> {
     ///
     /// This is also known as the baseband (BB) gain.
     ///
+    /// This can be called while receiving, e.g. for automatic gain control.
+    ///
     /// # Example
     ///
     /// Set the VGA gain to 16 dB (generally a reasonable gain to start with).
@@ -496,6 +841,18 @@ impl<MODE> HackRfOne<MODE> {
     /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
     /// radio.set_vga_gain(16)?;
     /// ```
+    ///
+    /// Turn the gain down part way through receiving.
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxMode, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<RxMode> = HackRfOne::new().unwrap().into_rx_mode()?;
+    /// let samples = radio.rx()?;
+    /// radio.set_vga_gain(8)?;
+    /// let quieter = radio.rx()?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
     pub fn set_vga_gain(&mut self, gain: u16) -> Result<(), Error> {
         if gain > 62 {
             Err(Error::Argument)
@@ -512,6 +869,19 @@ impl<MODE> HackRfOne<MODE> {
     /// Set the transmit VGA gain.
     ///
     /// Range 0 to 47dB in 1db steps.
+    ///
+    /// # Example
+    ///
+    /// Keep the output low for transmitting into an attenuator.
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// radio.set_amp_enable(false)?;
+    /// radio.set_txvga_gain(0)?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
     pub fn set_txvga_gain(&mut self, gain: u16) -> Result<(), Error> {
         if gain > 47 {
             Err(Error::Argument)
@@ -554,9 +924,7 @@ impl<MODE> HackRfOne<MODE> {
         self.check_api_version(Version::from_bcd(0x0102))?;
         self.write_control(Request::Reset, 0, 0, &[])?;
         Ok(HackRfOne {
-            dh: self.dh,
-            desc: self.desc,
-            interface: self.interface,
+            transport: self.transport,
             mode: UnknownMode,
             to: self.to,
         })
@@ -566,6 +934,18 @@ impl<MODE> HackRfOne<MODE> {
         self.write_control(Request::SetTransceiverMode, mode.into(), 0, &[])
     }
 
+    fn start_rx(&mut self, config: RxConfig) -> Result<Box<dyn BulkIn>, Error> {
+        if config.transfers == 0 || config.transfer_size == 0 || !config.transfer_size.is_multiple_of(512) {
+            return Err(Error::Argument);
+        }
+        self.set_transceiver_mode(TranscieverMode::Receive)?;
+        let mut queue = self.transport.bulk_in(0x81);
+        for _ in 0..config.transfers {
+            queue.submit(config.transfer_size);
+        }
+        Ok(queue)
+    }
+
     /// Change the radio mode to RX.
     ///
     /// # Example
@@ -576,38 +956,190 @@ impl<MODE> HackRfOne<MODE> {
     /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
     /// let mut radio: HackRfOne<RxMode> = radio.into_rx_mode()?;
     /// ```
-    pub fn into_rx_mode(mut self) -> Result<HackRfOne<RxMode>, Error> {
-        self.set_transceiver_mode(TranscieverMode::Receive)?;
-        let mut queue = self.interface.bulk_in_queue(0x81);
-        for _ in 0..4 {
-            queue.submit(RequestBuffer::new(262144));
-        }
-        let (tx, rx) = mpsc::channel();
+    pub fn into_rx_mode(self) -> Result<HackRfOne<RxMode>, Error> {
+        self.into_rx_mode_with(RxConfig::default())
+    }
+
+    /// Change the radio mode to RX with the given transfer settings.
+    ///
+    /// # Example
+    ///
+    /// Queue 8 transfers of 128 KiB.
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, RxMode, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// let config = RxConfig { transfers: 8, transfer_size: 131072, ..RxConfig::default() };
+    /// let mut radio: HackRfOne<RxMode> = radio.into_rx_mode_with(config)?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn into_rx_mode_with(mut self, config: RxConfig) -> Result<HackRfOne<RxMode>, Error> {
+        let mut queue = self.start_rx(config)?;
+        // bounded so that a reader that has stopped does not use up all the memory
+        let (tx, rx) = mpsc::sync_channel(config.backlog.max(1));
         let (cancel_tx, cancel_rx) = async_channel::bounded::<()>(1);
+        let stats = Arc::new(Mutex::new(RxStats::default()));
+        let thread_stats = stats.clone();
         let handle = thread::spawn(move || {
+            let mut last_completed: Option<Instant> = None;
+            let mut skipped = 0;
             loop {
                 let res = block_on(async {
-                    cancel_rx.recv().await.unwrap();
+                    // a closed channel means the radio was dropped, which also cancels
+                    let _ = cancel_rx.recv().await;
                     None
                 }.or(async {
-                    Some(queue.next_complete().await)
+                    Some(next_in(queue.as_mut()).await)
                 }));
 
                 let Some(res) = res else { return; };
+                let now = Instant::now();
+                {
+                    let mut stats = thread_stats.lock().unwrap();
+                    match res {
+                        Ok(_) => stats.transfers += 1,
+                        Err(_) => stats.failed += 1,
+                    }
+                    if let Some(last) = last_completed {
+                        stats.longest_gap = stats.longest_gap.max(now - last);
+                    }
+                }
+                last_completed = Some(now);
+                // nothing more will complete once the device is gone
+                let disconnected = matches!(res, Err(TransferError::Disconnected));
 
-                match tx.send(res) {
-                    Ok(_) => {
-                        queue.submit(RequestBuffer::new(262144));
-                    },
-                    Err(_) => return
+                match tx.try_send(res.map(|data| RxTransfer { data, skipped })) {
+                    Ok(_) => skipped = 0,
+                    // keep the radio streaming rather than wait for the reader, as it would
+                    // lose the samples anyway once its own buffer filled
+                    Err(mpsc::TrySendError::Full(_)) => {
+                        skipped += 1;
+                        thread_stats.lock().unwrap().dropped += 1;
+                    }
+                    Err(mpsc::TrySendError::Disconnected(_)) => return,
+                }
+                if disconnected {
+                    return;
                 }
+                queue.submit(config.transfer_size);
             }
         });
         Ok(HackRfOne {
-            dh: self.dh,
-            desc: self.desc,
-            interface: self.interface,
-            mode: RxMode(rx, cancel_tx, handle),
+            transport: self.transport,
+            mode: RxMode(rx, cancel_tx, handle, stats),
+            to: self.to,
+        })
+    }
+
+    /// Change the radio mode to RX, reading samples as an async [`Stream`] of buffers instead of
+    /// from a background thread.
+    ///
+    /// The stream works with any executor, including Tokio. It ends after reporting that the radio
+    /// was unplugged.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, RxStream, UnknownMode};
+    /// use futures_lite::StreamExt;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// let mut radio: HackRfOne<RxStream> = radio.into_rx_stream(RxConfig::default())?;
+    /// while let Some(buffer) = radio.next().await {
+    ///     println!("{} samples", buffer?.len() / 2);
+    /// }
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// # });
+    /// ```
+    pub fn into_rx_stream(mut self, config: RxConfig) -> Result<HackRfOne<RxStream>, Error> {
+        let queue = self.start_rx(config)?;
+        Ok(HackRfOne {
+            transport: self.transport,
+            mode: RxStream { queue, transfer_size: config.transfer_size, disconnected: false },
+            to: self.to,
+        })
+    }
+
+    /// Change the radio mode to sweep, where the radio retunes itself after every
+    /// [`SWEEP_BLOCK_SIZE`] bytes of samples, going round `config.ranges` until stopped.
+    ///
+    /// Needs firmware 2017.02.1 or later. The sample rate and gains are set beforehand as for
+    /// receiving.
+    ///
+    /// # Example
+    ///
+    /// Sweep 420 to 460 MHz the way `hackrf_sweep` does.
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, SweepConfig, SweepMode, SweepStyle, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// radio.set_sample_rate(20_000_000, 1)?;
+    /// let config = SweepConfig {
+    ///     ranges: vec![(420, 460)],
+    ///     step_width: 20_000_000,
+    ///     offset: 7_500_000,
+    ///     style: SweepStyle::Interleaved,
+    /// };
+    /// let mut radio: HackRfOne<SweepMode> = radio.into_sweep_mode(&config, RxConfig::default())?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn into_sweep_mode(mut self, config: &SweepConfig, transfers: RxConfig) -> Result<HackRfOne<SweepMode>, Error> {
+        self.check_api_version(Version::from_bcd(0x0102))?;
+        if config.ranges.is_empty() || config.ranges.len() > MAX_SWEEP_RANGES || config.step_width == 0
+            || transfers.transfers == 0 || transfers.transfer_size == 0
+            || !transfers.transfer_size.is_multiple_of(SWEEP_BLOCK_SIZE) {
+            return Err(Error::Argument);
+        }
+        let mut data = Vec::with_capacity(9 + config.ranges.len() * 4);
+        data.extend_from_slice(&config.step_width.to_le_bytes());
+        data.extend_from_slice(&config.offset.to_le_bytes());
+        data.push(config.style as u8);
+        for (start, stop) in &config.ranges {
+            data.extend_from_slice(&start.to_le_bytes());
+            data.extend_from_slice(&stop.to_le_bytes());
+        }
+        // the number of bytes recorded at each frequency
+        let dwell = SWEEP_BLOCK_SIZE as u32;
+        self.write_control(Request::InitSweep, (dwell & 0xFFFF) as u16, (dwell >> 16) as u16, &data)?;
+
+        self.set_transceiver_mode(TranscieverMode::RxSweep)?;
+        let mut queue = self.transport.bulk_in(0x81);
+        for _ in 0..transfers.transfers {
+            queue.submit(transfers.transfer_size);
+        }
+        Ok(HackRfOne {
+            transport: self.transport,
+            mode: SweepMode { queue, transfer_size: transfers.transfer_size },
+            to: self.to,
+        })
+    }
+
+    /// Change the radio mode to TX.
+    ///
+    /// Samples given to [`HackRfOne::tx`] are sent in transfers of `config.transfer_size` bytes,
+    /// with up to `config.transfers` queued at once.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, TxMode, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// let mut radio: HackRfOne<TxMode> = radio.into_tx_mode(RxConfig::default())?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn into_tx_mode(mut self, config: RxConfig) -> Result<HackRfOne<TxMode>, Error> {
+        if config.transfers == 0 || config.transfer_size == 0 || !config.transfer_size.is_multiple_of(512) {
+            return Err(Error::Argument);
+        }
+        self.set_transceiver_mode(TranscieverMode::Transmit)?;
+        let queue = self.transport.bulk_out(0x02);
+        Ok(HackRfOne {
+            transport: self.transport,
+            mode: TxMode { queue, config, pending: Vec::with_capacity(config.transfer_size) },
             to: self.to,
         })
     }
@@ -619,10 +1151,37 @@ impl HackRfOne<RxMode> {
     /// The data format is pairs of signed 8-bit IQ. Use the [`iq_to_cplx_i8`] or
     /// [`iq_to_cplx_f32`] helpers to convert the
     /// data to a more manageable format.
+    ///
+    /// Returns [`TransferError::Disconnected`] once the radio has been unplugged.
     #[cfg_attr(not(feature = "num-complex"), allow(rustdoc::broken_intra_doc_links))]
     pub fn rx(&mut self) -> Result<Vec<u8>, Error> {
-        let stuff = self.mode.0.recv().unwrap();
-        stuff.into_result().map_err(|e| e.into())
+        Ok(self.rx_transfer()?.data)
+    }
+
+    /// Receive data from the radio along with how many transfers were thrown away before it
+    /// because they were not read quickly enough.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxMode, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// let mut radio: HackRfOne<RxMode> = radio.into_rx_mode()?;
+    /// let transfer = radio.rx_transfer()?;
+    /// if transfer.skipped > 0 {
+    ///     println!("lost {} transfers", transfer.skipped);
+    /// }
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn rx_transfer(&mut self) -> Result<RxTransfer, Error> {
+        let stuff = self.mode.0.recv().map_err(|_| Error::Transfer(TransferError::Disconnected))?;
+        stuff.map_err(|e| e.into())
+    }
+
+    /// Counts of transfers received, failed and thrown away since RX mode started.
+    pub fn stats(&self) -> RxStats {
+        *self.mode.3.lock().unwrap()
     }
 
     /// Stop receiving.
@@ -638,14 +1197,183 @@ impl HackRfOne<RxMode> {
     /// ```
     pub fn stop_rx(mut self) -> Result<HackRfOne<UnknownMode>, Error> {
         self.set_transceiver_mode(TranscieverMode::Off)?;
-        let RxMode(rx, cancel_tx, handle) = self.mode;
-        cancel_tx.send_blocking(()).unwrap();
+        let RxMode(rx, cancel_tx, handle, _) = self.mode;
+        // the receive thread has already exited if the radio was unplugged
+        let _ = cancel_tx.send_blocking(());
         drop(rx);
         handle.join().unwrap();
         Ok(HackRfOne {
-            dh: self.dh,
-            desc: self.desc,
-            interface: self.interface,
+            transport: self.transport,
+            mode: UnknownMode,
+            to: self.to,
+        })
+    }
+}
+
+impl HackRfOne<RxStream> {
+    /// Stop receiving.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, RxStream, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// let mut radio: HackRfOne<RxStream> = radio.into_rx_stream(RxConfig::default())?;
+    /// radio.stop_rx()?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn stop_rx(mut self) -> Result<HackRfOne<UnknownMode>, Error> {
+        self.set_transceiver_mode(TranscieverMode::Off)?;
+        self.mode.queue.cancel_all();
+        Ok(HackRfOne {
+            transport: self.transport,
+            mode: UnknownMode,
+            to: self.to,
+        })
+    }
+}
+
+impl Stream for HackRfOne<RxStream> {
+    /// Pairs of signed 8-bit IQ, as from [`HackRfOne::rx`].
+    type Item = Result<Vec<u8>, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mode = &mut self.get_mut().mode;
+        if mode.disconnected {
+            return Poll::Ready(None);
+        }
+        let completion = ready!(mode.queue.poll_next(cx));
+        match completion {
+            Err(TransferError::Disconnected) => {
+                mode.disconnected = true;
+                Poll::Ready(Some(Err(Error::Transfer(TransferError::Disconnected))))
+            }
+            result => {
+                // keep the same number of transfers in flight
+                mode.queue.submit(mode.transfer_size);
+                Poll::Ready(Some(result.map_err(|e| e.into())))
+            }
+        }
+    }
+}
+
+impl HackRfOne<TxMode> {
+    /// Queue samples to transmit, in the same format as [`HackRfOne::rx`] returns them.
+    ///
+    /// Blocks while all transfers are in flight, so the radio's sample rate paces the caller.
+    /// Anything short of a whole transfer is held back until more samples or [`HackRfOne::flush`].
+    ///
+    /// # Example
+    ///
+    /// Transmit an unmodulated carrier for a second.
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, TxMode, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// radio.set_sample_rate(10_000_000, 1)?;
+    /// let mut radio: HackRfOne<TxMode> = radio.into_tx_mode(RxConfig::default())?;
+    /// radio.tx(&[127, 0].repeat(10_000_000))?;
+    /// radio.stop_tx()?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn tx(&mut self, samples: &[u8]) -> Result<(), Error> {
+        let transfer_size = self.mode.config.transfer_size;
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let needed = transfer_size - self.mode.pending.len();
+            let (now, later) = samples.split_at(needed.min(samples.len()));
+            self.mode.pending.extend_from_slice(now);
+            samples = later;
+            if self.mode.pending.len() == transfer_size {
+                let transfer = std::mem::replace(&mut self.mode.pending, Vec::with_capacity(transfer_size));
+                self.submit(transfer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send any held back samples, padded with silence to a multiple of 512 bytes, and wait for
+    /// every queued transfer to complete.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !self.mode.pending.is_empty() {
+            let mut transfer = std::mem::take(&mut self.mode.pending);
+            transfer.resize(transfer.len().next_multiple_of(512), 0);
+            self.submit(transfer)?;
+        }
+        while self.mode.queue.pending() > 0 {
+            self.complete()?;
+        }
+        Ok(())
+    }
+
+    fn submit(&mut self, transfer: Vec<u8>) -> Result<(), Error> {
+        while self.mode.queue.pending() >= self.mode.config.transfers {
+            self.complete()?;
+        }
+        self.mode.queue.submit(transfer);
+        Ok(())
+    }
+
+    fn complete(&mut self) -> Result<(), Error> {
+        block_on(next_out(self.mode.queue.as_mut()))?;
+        Ok(())
+    }
+
+    /// Send what is left and stop transmitting.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_libhackrf::{HackRfOne, RxConfig, TxMode, UnknownMode};
+    ///
+    /// let mut radio: HackRfOne<UnknownMode> = HackRfOne::new().unwrap();
+    /// let mut radio: HackRfOne<TxMode> = radio.into_tx_mode(RxConfig::default())?;
+    /// radio.stop_tx()?;
+    /// # Ok::<(), async_libhackrf::Error>(())
+    /// ```
+    pub fn stop_tx(mut self) -> Result<HackRfOne<UnknownMode>, Error> {
+        let flushed = self.flush();
+        // turn the transmitter off even if the last samples did not make it
+        self.set_transceiver_mode(TranscieverMode::Off)?;
+        self.mode.queue.cancel_all();
+        flushed?;
+        Ok(HackRfOne {
+            transport: self.transport,
+            mode: UnknownMode,
+            to: self.to,
+        })
+    }
+}
+
+impl HackRfOne<SweepMode> {
+    /// Wait for the next transfer of sweep samples, split up by the frequency they were recorded at.
+    ///
+    /// Blocks the radio did not mark with a frequency, which happens while it starts up, are
+    /// left out.
+    pub fn sweep_rx(&mut self) -> Result<Vec<SweepBlock>, Error> {
+        let buffer = block_on(next_in(self.mode.queue.as_mut()))?;
+        self.mode.queue.submit(self.mode.transfer_size);
+        Ok(buffer.chunks_exact(SWEEP_BLOCK_SIZE)
+            .filter(|block| block[0] == 0x7F && block[1] == 0x7F)
+            .map(|block| {
+                let mut frequency = [0; 8];
+                frequency.copy_from_slice(&block[2..SWEEP_HEADER_SIZE]);
+                SweepBlock {
+                    frequency: u64::from_le_bytes(frequency),
+                    samples: block[SWEEP_HEADER_SIZE..].to_vec(),
+                }
+            })
+            .collect())
+    }
+
+    /// Stop sweeping.
+    pub fn stop_sweep(mut self) -> Result<HackRfOne<UnknownMode>, Error> {
+        self.set_transceiver_mode(TranscieverMode::Off)?;
+        self.mode.queue.cancel_all();
+        Ok(HackRfOne {
+            transport: self.transport,
             mode: UnknownMode,
             to: self.to,
         })
@@ -0,0 +1,422 @@
+//! A pretend HackRF for testing without the hardware.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+use nusb::transfer::TransferError;
+use crate::transport::{BulkIn, BulkOut, Transport};
+use crate::{Request, TranscieverMode};
+
+/// A control request received by a [`SimulatedTransport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlRequest {
+    /// Vendor request number, as in libhackrf's `hackrf_vendor_request`.
+    pub request: u8,
+    /// Request value.
+    pub value: u16,
+    /// Request index.
+    pub index: u16,
+    /// Data sent with the request, empty for requests that read.
+    pub data: Vec<u8>,
+}
+
+/// What a [`SimulatedTransport`] has been told to do, `None` for settings that were never made.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimulatedSettings {
+    /// Center frequency in Hz.
+    pub frequency: Option<u64>,
+    /// Sample clock frequency and divider.
+    pub sample_rate: Option<(u32, u32)>,
+    /// Baseband filter bandwidth in Hz.
+    pub baseband_filter_bandwidth: Option<u32>,
+    /// LNA gain in dB.
+    pub lna_gain: Option<u16>,
+    /// VGA gain in dB.
+    pub vga_gain: Option<u16>,
+    /// TX VGA gain in dB.
+    pub txvga_gain: Option<u16>,
+    /// Whether the RF amplifier is on.
+    pub amp_enable: Option<bool>,
+    /// Whether antenna port power is on.
+    pub antenna_enable: Option<bool>,
+    /// Whether the radio is off (0), receiving (1), transmitting (2) or sweeping (5).
+    pub transceiver_mode: Option<u16>,
+}
+
+struct State {
+    requests: Vec<ControlRequest>,
+    settings: SimulatedSettings,
+    samples: Vec<u8>,
+    position: usize,
+    transmitted: Vec<u8>,
+    // bulk transfers left before the radio is unplugged
+    transfers_left: Option<usize>,
+    stalled: Vec<u8>,
+    unplugged: bool,
+    speed: Option<f64>,
+}
+
+/// A HackRF One that is not there, for testing code that uses [`HackRfOne`](crate::HackRfOne).
+///
+/// It answers like a HackRF One with recent firmware, keeps a log of the control requests it is
+/// sent, plays back samples in a loop when receiving and keeps what is transmitted. Clones share
+/// the same radio, so one can be handed to [`HackRfOne::with_transport`](crate::HackRfOne::with_transport)
+/// and another kept to look at afterwards or to pretend the radio was unplugged.
+///
+/// Samples are delivered in real time at the sample rate set on the radio, or as fast as
+/// possible before one is set.
+///
+/// # Example
+///
+/// ```
+/// use async_libhackrf::{HackRfOne, SimulatedTransport};
+///
+/// let simulated = SimulatedTransport::new().samples(vec![10, 246].repeat(1024));
+/// let mut radio = HackRfOne::with_transport(simulated.clone());
+/// radio.set_sample_rate(2_000_000, 1)?;
+/// radio.set_freq(436_850_000)?;
+/// radio.set_lna_gain(16)?;
+/// let mut radio = radio.into_rx_mode()?;
+/// assert_eq!(radio.rx()?[..2], [10, 246]);
+/// assert_eq!(simulated.settings().frequency, Some(436_850_000));
+///
+/// // transfers already received come first
+/// simulated.unplug();
+/// while radio.rx().is_ok() {}
+/// # Ok::<(), async_libhackrf::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct SimulatedTransport {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimulatedTransport {
+    /// A radio that receives nothing but zeros.
+    pub fn new() -> SimulatedTransport {
+        SimulatedTransport {
+            state: Arc::new(Mutex::new(State {
+                requests: Vec::new(),
+                settings: SimulatedSettings::default(),
+                samples: Vec::new(),
+                position: 0,
+                transmitted: Vec::new(),
+                transfers_left: None,
+                stalled: Vec::new(),
+                unplugged: false,
+                speed: Some(1.0),
+            })),
+        }
+    }
+
+    /// Receive `samples`, pairs of signed 8-bit IQ, over and over.
+    pub fn samples(self, samples: Vec<u8>) -> SimulatedTransport {
+        self.lock().samples = samples;
+        self
+    }
+
+    /// Receive the samples in a `hackrf_transfer` recording over and over.
+    pub fn play_file(self, path: impl AsRef<Path>) -> std::io::Result<SimulatedTransport> {
+        let samples = std::fs::read(path)?;
+        Ok(self.samples(samples))
+    }
+
+    /// Deliver samples `speed` times faster than real time, or as fast as they are read for `None`.
+    pub fn speed(self, speed: Option<f64>) -> SimulatedTransport {
+        self.lock().speed = speed;
+        self
+    }
+
+    /// Pretend the radio is unplugged after this many more bulk transfers.
+    pub fn disconnect_after(self, transfers: usize) -> SimulatedTransport {
+        self.lock().transfers_left = Some(transfers);
+        self
+    }
+
+    /// Make the radio stall every control request of this kind from now on.
+    pub fn stall(&self, request: Request) {
+        self.lock().stalled.push(request as u8);
+    }
+
+    /// Pretend the USB cable was pulled out. Everything fails with
+    /// [`TransferError::Disconnected`] until [`SimulatedTransport::plug_in`].
+    pub fn unplug(&self) {
+        self.lock().unplugged = true;
+    }
+
+    /// Pretend the radio was plugged back in. Like a real one it has to be opened again, with a
+    /// new [`HackRfOne`](crate::HackRfOne).
+    pub fn plug_in(&self) {
+        let mut state = self.lock();
+        state.unplugged = false;
+        state.transfers_left = None;
+    }
+
+    /// Whether the radio is pretending to be unplugged.
+    pub fn is_unplugged(&self) -> bool {
+        self.lock().unplugged
+    }
+
+    /// Every control request received so far, oldest first.
+    pub fn requests(&self) -> Vec<ControlRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// The latest settings made.
+    pub fn settings(&self) -> SimulatedSettings {
+        self.lock().settings.clone()
+    }
+
+    /// Everything transmitted so far, as pairs of signed 8-bit IQ.
+    pub fn transmitted(&self) -> Vec<u8> {
+        self.lock().transmitted.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Log a request and check whether it gets through.
+    fn request(&self, request: u8, value: u16, index: u16, data: &[u8]) -> Result<MutexGuard<'_, State>, TransferError> {
+        let mut state = self.lock();
+        if state.unplugged {
+            return Err(TransferError::Disconnected);
+        }
+        state.requests.push(ControlRequest { request, value, index, data: data.to_vec() });
+        if state.stalled.contains(&request) {
+            return Err(TransferError::Stall);
+        }
+        Ok(state)
+    }
+}
+
+impl Default for SimulatedTransport {
+    fn default() -> SimulatedTransport {
+        SimulatedTransport::new()
+    }
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+impl Transport for SimulatedTransport {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8], _timeout: Duration) -> Result<usize, TransferError> {
+        let mut state = self.request(request, value, index, &[])?;
+        buf.fill(0);
+        let settings = &mut state.settings;
+        match request {
+            r if r == Request::BoardIdRead as u8 => buf[0] = 2,
+            r if r == Request::VersionStringRead as u8 => {
+                let version = b"simulated";
+                let n = version.len().min(buf.len());
+                buf[..n].copy_from_slice(&version[..n]);
+                return Ok(n);
+            }
+            r if r == Request::BoardPartidSerialnoRead as u8 => {
+                for (i, word) in [0xa000cb3c_u32, 0x00574f4b, 0, 0, 0x5a6f7e8d, 0x0123abcd].iter().enumerate() {
+                    buf[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+                }
+            }
+            r if r == Request::CpldChecksum as u8 => buf[..4].copy_from_slice(&0x5d2c_4e3bu32.to_le_bytes()),
+            // the gain requests reply with whether the gain was accepted
+            r if r == Request::SetLnaGain as u8 => {
+                settings.lna_gain = Some(index);
+                buf[0] = 1;
+            }
+            r if r == Request::SetVgaGain as u8 => {
+                settings.vga_gain = Some(index);
+                buf[0] = 1;
+            }
+            r if r == Request::SetTxvgaGain as u8 => {
+                settings.txvga_gain = Some(index);
+                buf[0] = 1;
+            }
+            _ => {}
+        }
+        Ok(buf.len())
+    }
+
+    fn control_out(&self, request: u8, value: u16, index: u16, buf: &[u8], _timeout: Duration) -> Result<usize, TransferError> {
+        let mut state = self.request(request, value, index, buf)?;
+        let settings = &mut state.settings;
+        match request {
+            r if r == Request::SetFreq as u8 && buf.len() == 8 => {
+                settings.frequency = Some(u32_at(buf, 0) as u64 * 1_000_000 + u32_at(buf, 4) as u64);
+            }
+            r if r == Request::SampleRateSet as u8 && buf.len() == 8 => {
+                settings.sample_rate = Some((u32_at(buf, 0), u32_at(buf, 4)));
+            }
+            r if r == Request::BasebandFilterBandwidthSet as u8 => {
+                settings.baseband_filter_bandwidth = Some(value as u32 | (index as u32) << 16);
+            }
+            r if r == Request::AmpEnable as u8 => settings.amp_enable = Some(value != 0),
+            r if r == Request::AntennaEnable as u8 => settings.antenna_enable = Some(value != 0),
+            r if r == Request::SetTransceiverMode as u8 => {
+                settings.transceiver_mode = Some(value);
+                if value == TranscieverMode::Off as u16 {
+                    state.position = 0;
+                }
+            }
+            _ => {}
+        }
+        Ok(buf.len())
+    }
+
+    fn bulk_in(&self, _endpoint: u8) -> Box<dyn BulkIn> {
+        Box::new(SimulatedBulkIn {
+            radio: self.clone(),
+            pending: VecDeque::new(),
+            started: None,
+            delivered: 0,
+            timer: None,
+        })
+    }
+
+    fn bulk_out(&self, _endpoint: u8) -> Box<dyn BulkOut> {
+        Box::new(SimulatedBulkOut { radio: self.clone(), pending: VecDeque::new() })
+    }
+
+    fn device_version(&self) -> u16 {
+        0x0104
+    }
+}
+
+/// Uses up one bulk transfer, failing it if the radio is or has just been unplugged.
+fn use_transfer(state: &mut State) -> Result<(), TransferError> {
+    if state.unplugged {
+        return Err(TransferError::Disconnected);
+    }
+    match &mut state.transfers_left {
+        Some(0) => {
+            state.unplugged = true;
+            Err(TransferError::Disconnected)
+        }
+        Some(left) => {
+            *left -= 1;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+struct SimulatedBulkIn {
+    radio: SimulatedTransport,
+    // length of each transfer, or None once cancelled
+    pending: VecDeque<Option<usize>>,
+    started: Option<Instant>,
+    delivered: u64,
+    // when the oldest transfer is due and the waker to call then, once a timer has been started
+    timer: Option<(Instant, Arc<Mutex<Waker>>)>,
+}
+
+impl SimulatedBulkIn {
+    /// Wake `cx` at `due`, from a thread so that whatever is polling is not held up meanwhile.
+    fn wake_at(&mut self, due: Instant, cx: &mut Context<'_>) {
+        if let Some((timer_due, waker)) = &self.timer {
+            if *timer_due == due {
+                // polled again before the timer went off, perhaps from another task
+                waker.lock().unwrap().clone_from(cx.waker());
+                return;
+            }
+        }
+        let waker = Arc::new(Mutex::new(cx.waker().clone()));
+        self.timer = Some((due, waker.clone()));
+        thread::spawn(move || {
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            waker.lock().unwrap().wake_by_ref();
+        });
+    }
+}
+
+impl BulkIn for SimulatedBulkIn {
+    fn submit(&mut self, len: usize) {
+        self.pending.push_back(Some(len));
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>, TransferError>> {
+        // like a real queue with nothing submitted, this never completes
+        let Some(&transfer) = self.pending.front() else {
+            return Poll::Pending;
+        };
+        let Some(len) = transfer else {
+            self.pending.pop_front();
+            return Poll::Ready(Err(TransferError::Cancelled));
+        };
+
+        // wait until the radio would have recorded the samples
+        let (sample_rate, speed) = {
+            let state = self.radio.lock();
+            (state.settings.sample_rate, state.speed)
+        };
+        if let (Some((hz, divider)), Some(speed)) = (sample_rate, speed) {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let seconds = (self.delivered + len as u64) as f64 / 2.0 * divider as f64 / hz as f64 / speed;
+            let due = started + Duration::from_secs_f64(seconds);
+            if Instant::now() < due {
+                self.wake_at(due, cx);
+                return Poll::Pending;
+            }
+        }
+
+        self.pending.pop_front();
+        self.timer = None;
+        let mut state = self.radio.lock();
+        if let Err(e) = use_transfer(&mut state) {
+            return Poll::Ready(Err(e));
+        }
+        let mut data = vec![0; len];
+        if !state.samples.is_empty() {
+            for byte in data.iter_mut() {
+                *byte = state.samples[state.position];
+                state.position = (state.position + 1) % state.samples.len();
+            }
+        }
+        self.delivered += len as u64;
+        Poll::Ready(Ok(data))
+    }
+
+    fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn cancel_all(&mut self) {
+        self.pending.iter_mut().for_each(|transfer| *transfer = None);
+    }
+}
+
+struct SimulatedBulkOut {
+    radio: SimulatedTransport,
+    pending: VecDeque<Option<Vec<u8>>>,
+}
+
+impl BulkOut for SimulatedBulkOut {
+    fn submit(&mut self, data: Vec<u8>) {
+        self.pending.push_back(Some(data));
+    }
+
+    fn poll_next(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), TransferError>> {
+        let Some(transfer) = self.pending.pop_front() else {
+            return Poll::Pending;
+        };
+        let Some(data) = transfer else {
+            return Poll::Ready(Err(TransferError::Cancelled));
+        };
+        let mut state = self.radio.lock();
+        if let Err(e) = use_transfer(&mut state) {
+            return Poll::Ready(Err(e));
+        }
+        state.transmitted.extend_from_slice(&data);
+        Poll::Ready(Ok(()))
+    }
+
+    fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn cancel_all(&mut self) {
+        self.pending.iter_mut().for_each(|transfer| *transfer = None);
+    }
+}
@@ -0,0 +1,155 @@
+//! The USB link to a HackRF, behind a trait so that it can be simulated.
+
+use std::future::{poll_fn, Future};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use nusb::transfer::{Control, ControlType, Queue, Recipient, RequestBuffer, TransferError};
+
+/// Carries requests and samples between [`HackRfOne`](crate::HackRfOne) and a radio.
+///
+/// [`HackRfOne::new`](crate::HackRfOne::new) and friends use USB; see
+/// [`SimulatedTransport`](crate::SimulatedTransport) for testing without a radio.
+pub trait Transport: Send {
+    /// Send a vendor request to the device and read its reply into `buf`, returning the number of
+    /// bytes read.
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8], timeout: Duration) -> Result<usize, TransferError>;
+
+    /// Send a vendor request with `buf` to the device, returning the number of bytes written.
+    fn control_out(&self, request: u8, value: u16, index: u16, buf: &[u8], timeout: Duration) -> Result<usize, TransferError>;
+
+    /// Start a queue of transfers from a bulk IN endpoint.
+    fn bulk_in(&self, endpoint: u8) -> Box<dyn BulkIn>;
+
+    /// Start a queue of transfers to a bulk OUT endpoint.
+    fn bulk_out(&self, endpoint: u8) -> Box<dyn BulkOut>;
+
+    /// Device version (`bcdDevice`) from the USB descriptor.
+    fn device_version(&self) -> u16;
+}
+
+/// Transfers being read from the device, completed in the order they were submitted.
+pub trait BulkIn: Send {
+    /// Queue a transfer of `len` bytes.
+    fn submit(&mut self, len: usize);
+
+    /// Wait for the oldest transfer to complete.
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>, TransferError>>;
+
+    /// Number of transfers submitted and not yet returned by `poll_next`.
+    fn pending(&self) -> usize;
+
+    /// Cancel every pending transfer. They still complete, with [`TransferError::Cancelled`].
+    fn cancel_all(&mut self);
+}
+
+/// Transfers being written to the device, completed in the order they were submitted.
+pub trait BulkOut: Send {
+    /// Queue `data` to be sent.
+    fn submit(&mut self, data: Vec<u8>);
+
+    /// Wait for the oldest transfer to complete.
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), TransferError>>;
+
+    /// Number of transfers submitted and not yet returned by `poll_next`.
+    fn pending(&self) -> usize;
+
+    /// Cancel every pending transfer. They still complete, with [`TransferError::Cancelled`].
+    fn cancel_all(&mut self);
+}
+
+pub(crate) fn next_in(queue: &mut dyn BulkIn) -> impl Future<Output = Result<Vec<u8>, TransferError>> + '_ {
+    poll_fn(move |cx| queue.poll_next(cx))
+}
+
+pub(crate) fn next_out(queue: &mut dyn BulkOut) -> impl Future<Output = Result<(), TransferError>> + '_ {
+    poll_fn(move |cx| queue.poll_next(cx))
+}
+
+/// A HackRF attached over USB.
+pub(crate) struct UsbTransport {
+    // kept open for as long as the interface is claimed
+    _device: nusb::Device,
+    interface: nusb::Interface,
+    info: nusb::DeviceInfo,
+}
+
+impl UsbTransport {
+    /// Open `info`, failing if another program is using it.
+    pub(crate) fn open(info: nusb::DeviceInfo) -> Option<UsbTransport> {
+        let device = info.open().ok()?;
+        let interface = device.claim_interface(0).ok()?;
+        Some(UsbTransport { _device: device, interface, info })
+    }
+}
+
+fn vendor_request(request: u8, value: u16, index: u16) -> Control {
+    Control {
+        control_type: ControlType::Vendor,
+        recipient: Recipient::Device,
+        request,
+        value,
+        index,
+    }
+}
+
+impl Transport for UsbTransport {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8], timeout: Duration) -> Result<usize, TransferError> {
+        self.interface.control_in_blocking(vendor_request(request, value, index), buf, timeout)
+    }
+
+    fn control_out(&self, request: u8, value: u16, index: u16, buf: &[u8], timeout: Duration) -> Result<usize, TransferError> {
+        self.interface.control_out_blocking(vendor_request(request, value, index), buf, timeout)
+    }
+
+    fn bulk_in(&self, endpoint: u8) -> Box<dyn BulkIn> {
+        Box::new(UsbBulkIn(self.interface.bulk_in_queue(endpoint)))
+    }
+
+    fn bulk_out(&self, endpoint: u8) -> Box<dyn BulkOut> {
+        Box::new(UsbBulkOut(self.interface.bulk_out_queue(endpoint)))
+    }
+
+    fn device_version(&self) -> u16 {
+        self.info.device_version()
+    }
+}
+
+struct UsbBulkIn(Queue<RequestBuffer>);
+
+impl BulkIn for UsbBulkIn {
+    fn submit(&mut self, len: usize) {
+        self.0.submit(RequestBuffer::new(len));
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>, TransferError>> {
+        self.0.poll_next(cx).map(|completion| completion.into_result())
+    }
+
+    fn pending(&self) -> usize {
+        self.0.pending()
+    }
+
+    fn cancel_all(&mut self) {
+        self.0.cancel_all();
+    }
+}
+
+struct UsbBulkOut(Queue<Vec<u8>>);
+
+impl BulkOut for UsbBulkOut {
+    fn submit(&mut self, data: Vec<u8>) {
+        self.0.submit(data);
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), TransferError>> {
+        self.0.poll_next(cx).map(|completion| completion.status)
+    }
+
+    fn pending(&self) -> usize {
+        self.0.pending()
+    }
+
+    fn cancel_all(&mut self) {
+        self.0.cancel_all();
+    }
+}
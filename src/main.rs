@@ -1,12 +1,397 @@
 #[macro_use] extern crate rocket;
 
+use rocket::fairing::AdHoc;
 use rocket::fs::{FileServer, relative};
-use rocket::get;
-use std::sync::Mutex;
-use std::thread;
-use telemega::DecodedPacket;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::{get, post, State};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use clap::{Args, Parser, Subcommand};
+use telemega::{BandScan, Channel, DecoderConfig, DecoderHandle, GainControl, GainSettings, HackRFIQSource, HackRFSweeper, HackRFTransmitter, IQError, HACKRF_SAMPLE_RATES, RawRecording, ReceivedPacket, ReceiverConfig, SampleFormat, SourceCommand, SourceConfig, TelemetryModulator, TestFlight};
 
-static mut QUEUE: Mutex<Vec<DecodedPacket>> = Mutex::new(Vec::new());
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Arguments {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Frequencies to decode in Hz, optionally labelled like 436750000=SustainerAL0
+    #[arg(value_parser = parse_channel, required_unless_present_any = ["list_devices", "cpld_checksum"])]
+    frequencies: Vec<Channel>,
+    /// Serial number of the HackRF to use, or its last few digits
+    #[arg(short, long, value_name = "SERIAL", conflicts_with_all = ["file", "rtl_tcp"])]
+    device: Option<String>,
+    /// HackRF gain control: manual:LNA,VGA in dB (LNA 0-40 in steps of 8, VGA 0-62 in steps of 2),
+    /// peak[:LOW,HIGH[,WINDOW_MS]] or rms[:TARGET_DBFS[,HYSTERESIS_DB[,WINDOW_MS]]], adjustable later
+    /// from the web page
+    #[arg(short, long, value_name = "MODE", conflicts_with_all = ["file", "rtl_tcp"])]
+    gain: Option<GainControl>,
+    /// Leave the HackRF's RF amplifier off
+    #[arg(long, conflicts_with_all = ["file", "rtl_tcp"])]
+    no_amp: bool,
+    /// Power a mast mounted LNA through the HackRF's antenna port. This puts 3.3 V on the antenna
+    /// cable, which can damage equipment that does not expect it. Without it antenna power cannot
+    /// be switched on from the web page either
+    #[arg(long, conflicts_with_all = ["file", "rtl_tcp"])]
+    antenna_power: bool,
+    /// Also decode another radio, given as NAME,SOURCE,FREQ[,FREQ...] where SOURCE is hackrf,
+    /// hackrf:SERIAL, rtl_tcp:HOST:PORT or a recording
+    #[arg(long, value_parser = parse_receiver, value_name = "NAME,SOURCE,FREQ")]
+    receiver: Vec<ReceiverConfig>,
+    /// Print the serial numbers of the attached HackRFs and exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Print the checksum of the HackRF's CPLD configuration and exit, only while nothing else is
+    /// using the radio
+    #[arg(long, conflicts_with_all = ["file", "rtl_tcp"])]
+    cpld_checksum: bool,
+    /// Decode a recording instead of a live HackRF, or samples piped in on stdin with -
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+    /// Receive from an rtl_tcp server at host:port instead of a local HackRF
+    #[arg(long, value_name = "ADDRESS", conflicts_with = "file")]
+    rtl_tcp: Option<String>,
+    /// Manual rtl_tcp tuner gain in dB, the tuner's AGC is used otherwise
+    #[arg(long, requires = "rtl_tcp")]
+    rtl_gain: Option<f64>,
+    /// Sample format of --file (cs8, cu8, cs16, cf32), guessed from the extension if not given
+    #[arg(long)]
+    format: Option<SampleFormat>,
+    /// Sample rate in Hz of a raw --file, or of the HackRF: 8000000, 10000000, 12500000, 16000000 or 20000000
+    #[arg(short, long)]
+    sample_rate: Option<f64>,
+    /// Center frequency to tune to, defaults to the recording's center or just above the highest channel
+    #[arg(short, long)]
+    center: Option<f64>,
+    /// Play --file back in real time instead of as fast as possible
+    #[arg(long)]
+    realtime: bool,
+    /// Real time playback speed multiplier, e.g. 0.5, 2 or 10 (implies --realtime)
+    #[arg(long, value_parser = parse_speed)]
+    speed: Option<f64>,
+    /// Seconds into --file to start decoding from
+    #[arg(long, value_parser = parse_offset)]
+    start: Option<f64>,
+    /// Save received samples as a SigMF recording annotated with the decoded packets
+    #[arg(short, long)]
+    record: Option<PathBuf>,
+    /// Also tee the raw samples to rotating <base>-NNN.cs8 files with a .json sidecar each
+    #[arg(long, value_name = "BASE")]
+    raw_record: Option<PathBuf>,
+    /// Start a new raw recording file after this many megabytes
+    #[arg(long, requires = "raw_record", value_parser = clap::value_parser!(u64).range(1..=u64::MAX / 1_000_000))]
+    raw_max_mb: Option<u64>,
+    /// Start a new raw recording file after this many seconds of samples
+    #[arg(long, requires = "raw_record", value_parser = parse_seconds)]
+    raw_max_secs: Option<f64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Transmit a pretend flight's telemetry to test a receiver on the bench, only ever into a
+    /// dummy load or attenuator
+    Transmit(TransmitArguments),
+    /// Sweep the 70 cm band for flight computers that are transmitting and suggest the
+    /// frequencies to decode
+    Scan(ScanArguments),
+}
+
+#[derive(Args, Debug)]
+struct TransmitArguments {
+    /// Frequency to transmit on in Hz
+    frequency: f64,
+    /// Serial number of the HackRF to transmit with, or its last few digits
+    #[arg(short, long, value_name = "SERIAL")]
+    device: Option<String>,
+    /// TX VGA gain in dB, 0 to 47
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u16).range(0..=47))]
+    tx_gain: u16,
+    /// Also turn on the 14 dB RF amplifier
+    #[arg(long)]
+    amp: bool,
+    /// Flight computer serial number to send as
+    #[arg(long, default_value_t = 1)]
+    serial: u16,
+    /// Callsign to put in the config packets
+    #[arg(long, default_value = "N0CALL")]
+    callsign: String,
+    /// Seconds between packets
+    #[arg(long, default_value_t = 0.5, value_parser = parse_seconds)]
+    interval: f64,
+    /// Stop after this many seconds instead of running until Ctrl-C
+    #[arg(long, value_parser = parse_seconds)]
+    duration: Option<f64>,
+    /// Sample rate in Hz: 8000000, 10000000, 12500000, 16000000 or 20000000
+    #[arg(short, long, default_value_t = telemega::DEFAULT_SAMPLE_RATE, value_parser = parse_hackrf_sample_rate)]
+    sample_rate: f64,
+}
+
+#[derive(Args, Debug)]
+struct ScanArguments {
+    /// Lowest frequency to sweep in Hz
+    #[arg(long, default_value_t = 420_000_000.0)]
+    from: f64,
+    /// Highest frequency to sweep in Hz
+    #[arg(long, default_value_t = 450_000_000.0)]
+    to: f64,
+    /// Seconds to listen for, long enough to catch a few packets from every flight computer
+    #[arg(long, default_value_t = 10.0)]
+    seconds: f64,
+    /// How far above the noise floor in dB a signal has to peak to be listed
+    #[arg(long, default_value_t = 12.0)]
+    threshold: f32,
+    /// Serial number of the HackRF to sweep with, or its last few digits
+    #[arg(short, long, value_name = "SERIAL")]
+    device: Option<String>,
+    /// LNA gain in dB, 0 to 40 in steps of 8
+    #[arg(long, default_value_t = 16)]
+    lna: u16,
+    /// VGA gain in dB, 0 to 62 in steps of 2
+    #[arg(long, default_value_t = 20)]
+    vga: u16,
+    /// Turn on the 14 dB RF amplifier
+    #[arg(long)]
+    amp: bool,
+}
+
+fn scan(args: &ScanArguments) -> Result<(), IQError> {
+    let gain = GainSettings { lna: args.lna, vga: args.vga, amp: args.amp };
+    let mut sweeper = HackRFSweeper::open(args.device.as_deref(), args.from, args.to, gain)?;
+    let (start, stop) = sweeper.range();
+    println!("Sweeping {:.0} to {:.0} MHz for {} s", start / 1e6, stop / 1e6, args.seconds);
+
+    let mut scan = BandScan::new();
+    let started = Instant::now();
+    while started.elapsed().as_secs_f64() < args.seconds {
+        scan.add(&sweeper.sweep()?);
+    }
+    sweeper.stop()?;
+
+    let carriers: Vec<_> = scan.carriers(args.threshold).into_iter()
+        .filter(|carrier| (args.from..=args.to).contains(&carrier.frequency))
+        .collect();
+    println!("{} sweeps, {} signals", scan.sweeps(), carriers.len());
+    for carrier in &carriers {
+        println!(
+            "{:.3} MHz {:5.1} dB above noise, {:3.0} kHz wide{}",
+            carrier.frequency / 1e6,
+            carrier.snr,
+            carrier.bandwidth / 1e3,
+            if carrier.telemetry { ", looks like telemetry" } else { "" },
+        );
+    }
+    // AltOS frequencies are set in kHz, but the sweep cannot tell them apart more finely than this
+    let suggested: Vec<String> = carriers.iter()
+        .filter(|carrier| carrier.telemetry)
+        .map(|carrier| format!("{:.0}", (carrier.frequency / 10_000.0).round() * 10_000.0))
+        .collect();
+    if suggested.is_empty() {
+        println!("No telemetry found");
+    } else {
+        println!("Try: tele-decode {}", suggested.join(" "));
+    }
+    Ok(())
+}
+
+/// How far the transmitter is tuned from the channel, keeping the packets clear of the HackRF's
+/// carrier leakage.
+const TX_OFFSET: f64 = 200_000.0;
+
+fn transmit(args: &TransmitArguments) -> Result<(), IQError> {
+    let center = args.frequency + TX_OFFSET;
+    let mut radio = HackRFTransmitter::open(args.device.as_deref(), center, args.sample_rate, args.tx_gain, args.amp)?;
+    // the transmitter is switched off however this ends, rather than left sending whatever it has
+    let stopping = Arc::new(AtomicBool::new(false));
+    let handler_stopping = stopping.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_stopping.store(true, Ordering::Relaxed)) {
+        radio.stop()?;
+        return Err(IQError::Io(std::io::Error::other(e)));
+    }
+    println!("Transmitting serial {} on {} Hz every {} s", args.serial, args.frequency, args.interval);
+    let sent = transmit_flight(&mut radio, args, &stopping);
+    radio.stop().and(sent)
+}
+
+fn transmit_flight(radio: &mut HackRFTransmitter, args: &TransmitArguments, stopping: &AtomicBool) -> Result<(), IQError> {
+    let mut modulator = TelemetryModulator::new(radio.sample_rate(), telemega::DEFAULT_BAUD, -TX_OFFSET);
+    let mut flight = TestFlight::new(args.serial, args.callsign.as_str());
+    let interval = Duration::from_secs_f64(args.interval);
+
+    let mut time = 0.0;
+    while !stopping.load(Ordering::Relaxed) && args.duration.is_none_or(|duration| time < duration) {
+        let packet = modulator.modulate(&flight.next_packet(time));
+        let gap = interval.saturating_sub(Duration::from_secs_f64(packet.len() as f64 / radio.sample_rate()));
+        // the radio's sample rate paces the loop
+        radio.send(&packet)?;
+        let mut silence = (gap.as_secs_f64() * radio.sample_rate()) as usize;
+        while silence > 0 && !stopping.load(Ordering::Relaxed) {
+            let samples = silence.min(radio.transfer_samples());
+            radio.send_silence(samples)?;
+            silence -= samples;
+        }
+        time += args.interval;
+    }
+    Ok(())
+}
+
+fn parse_seconds(arg: &str) -> Result<f64, String> {
+    let seconds: f64 = arg.parse().map_err(|e| format!("invalid number: {e}"))?;
+    if seconds > 0.0 && Duration::try_from_secs_f64(seconds).is_ok() {
+        Ok(seconds)
+    } else {
+        Err("must be a positive number of seconds".into())
+    }
+}
+
+fn parse_offset(arg: &str) -> Result<f64, String> {
+    let seconds: f64 = arg.parse().map_err(|e| format!("invalid number: {e}"))?;
+    if Duration::try_from_secs_f64(seconds).is_ok() {
+        Ok(seconds)
+    } else {
+        Err("must be a number of seconds, 0 or more".into())
+    }
+}
+
+fn parse_speed(arg: &str) -> Result<f64, String> {
+    let speed: f64 = arg.parse().map_err(|e| format!("invalid number: {e}"))?;
+    if speed > 0.0 && speed.is_finite() {
+        Ok(speed)
+    } else {
+        Err("must be a positive multiplier".into())
+    }
+}
+
+fn parse_hackrf_sample_rate(arg: &str) -> Result<f64, String> {
+    let sample_rate: f64 = arg.parse().map_err(|e| format!("invalid sample rate: {e}"))?;
+    if HACKRF_SAMPLE_RATES.contains(&sample_rate) {
+        Ok(sample_rate)
+    } else {
+        Err(format!("the HackRF only runs at {HACKRF_SAMPLE_RATES:?} Hz"))
+    }
+}
+
+fn parse_channel(arg: &str) -> Result<Channel, String> {
+    let (frequency, label) = match arg.split_once('=') {
+        Some((frequency, label)) => (frequency, Some(label)),
+        None => (arg, None),
+    };
+    let frequency: f64 = frequency.parse().map_err(|e| format!("invalid frequency: {e}"))?;
+    Ok(match label {
+        Some(label) => Channel::labelled(frequency, label),
+        None => Channel::new(frequency),
+    })
+}
+
+fn parse_receiver(arg: &str) -> Result<ReceiverConfig, String> {
+    let mut parts = arg.split(',');
+    let (Some(name), Some(source)) = (parts.next(), parts.next()) else {
+        return Err("expected NAME,SOURCE,FREQ[,FREQ...]".into());
+    };
+    let source = match source.split_once(':') {
+        _ if source == "hackrf" => SourceConfig::default(),
+        Some(("hackrf", serial)) => SourceConfig::hackrf(serial),
+        Some(("rtl_tcp", address)) => SourceConfig::RtlTcp { address: address.into(), gain: None },
+        _ => SourceConfig::file(source),
+    };
+    let mut receiver = ReceiverConfig::new(name, source);
+    for channel in parts {
+        receiver = receiver.channel(parse_channel(channel)?);
+    }
+    if receiver.channels().is_empty() {
+        return Err(format!("receiver {name} needs at least one frequency"));
+    }
+    Ok(receiver)
+}
+
+impl From<Arguments> for DecoderConfig {
+    fn from(args: Arguments) -> DecoderConfig {
+        let mut config = args.frequencies.into_iter()
+            .fold(DecoderConfig::new(Vec::new()), DecoderConfig::channel);
+        for receiver in args.receiver {
+            config = config.receiver(receiver);
+        }
+        let gain = args.gain.unwrap_or_default().amp(!args.no_amp);
+        config = config.source(SourceConfig::HackRf { serial: args.device, gain, antenna_power: args.antenna_power });
+        if let Some(path) = args.file {
+            config = config.source(SourceConfig::File { path, format: args.format });
+        }
+        if let Some(address) = args.rtl_tcp {
+            config = config.source(SourceConfig::RtlTcp { address, gain: args.rtl_gain });
+        }
+        if let Some(sample_rate) = args.sample_rate {
+            config = config.sample_rate(sample_rate);
+        }
+        if let Some(center) = args.center {
+            config = config.center(center);
+        }
+        if let Some(speed) = args.speed.or(args.realtime.then_some(1.0)) {
+            config = config.playback_speed(speed);
+        }
+        if let Some(start) = args.start {
+            config = config.start_offset(Duration::from_secs_f64(start));
+        }
+        if let Some(path) = args.record {
+            config = config.record(path);
+        }
+        if let Some(base) = args.raw_record {
+            let mut recording = RawRecording::new(base);
+            if let Some(mb) = args.raw_max_mb {
+                // clap keeps this from overflowing
+                recording = recording.max_file_bytes(mb * 1_000_000);
+            }
+            if let Some(secs) = args.raw_max_secs {
+                recording = recording.max_file_duration(Duration::from_secs_f64(secs));
+            }
+            config = config.raw_recording(recording);
+        }
+        config
+    }
+}
+
+fn list_devices() {
+    let devices = async_libhackrf::list_devices();
+    if devices.is_empty() {
+        println!("No HackRFs found");
+    }
+    for device in devices {
+        match (device.board_id, device.firmware) {
+            (Some(board_id), Some(firmware)) => {
+                let board = async_libhackrf::board_name(board_id);
+                println!("{} {board} (board id {board_id}) firmware {firmware} API {}", device.serial, device.device_version);
+            }
+            _ => println!("{} (in use)", device.serial),
+        }
+    }
+}
+
+static mut QUEUE: Mutex<Vec<ReceivedPacket>> = Mutex::new(Vec::new());
+static DECODER: Mutex<Option<DecoderHandle>> = Mutex::new(None);
+
+fn start_decoding(config: &DecoderConfig) -> Result<DecoderHandle, IQError> {
+    telemega::start_decoders(config.clone(), |packet| {
+        // println!("{packet:?}");
+        println!("{} {:.1} dBFS", packet.packet.crc_match(), packet.rssi);
+        if let Ok(queued) = unsafe { QUEUE.get_mut() } {
+            queued.push(packet);
+        } else {
+            eprintln!("Failed to queue packet");
+        }
+    })
+}
+
+fn stop_decoding() -> bool {
+    let handle = DECODER.lock().unwrap().take();
+    if let Some(handle) = handle {
+        if let Err(e) = handle.shutdown() {
+            eprintln!("Decoding stopped: {e}");
+        }
+        true
+    } else {
+        false
+    }
+}
 
 #[get("/getdata")]
 fn data() -> String {
@@ -22,21 +407,148 @@ fn data() -> String {
     }
 }
 
+#[get("/events")]
+fn events() -> String {
+    let events = DECODER.lock().unwrap()
+        .as_ref()
+        .map(DecoderHandle::events)
+        .unwrap_or_default();
+    serde_json::to_string(&events).unwrap()
+}
+
+/// What the radios are and how they are set up, to check before a launch.
+#[get("/status")]
+fn status() -> String {
+    let decoder = DECODER.lock().unwrap();
+    let status = serde_json::json!({
+        "running": decoder.as_ref().is_some_and(DecoderHandle::is_running),
+        "receivers": decoder.as_ref().map(DecoderHandle::diagnostics).unwrap_or_default(),
+    });
+    status.to_string()
+}
+
+#[post("/decoder/start")]
+fn start(config: &State<DecoderConfig>) -> Result<&'static str, Custom<String>> {
+    let mut decoder = DECODER.lock().unwrap();
+    if decoder.as_ref().is_some_and(DecoderHandle::is_running) {
+        return Ok("already running");
+    }
+    if let Some(finished) = decoder.take() {
+        if let Err(e) = finished.join() {
+            eprintln!("Decoding stopped: {e}");
+        }
+    }
+    let handle = start_decoding(config)
+        .map_err(|e| Custom(Status::InternalServerError, format!("failed to start: {e}")))?;
+    *decoder = Some(handle);
+    Ok("started")
+}
+
+/// Change how the gain is set, e.g. `{"mode": "manual", "lna": 32, "vga": 20, "amp": true}`.
+#[post("/gain?<receiver>", data = "<body>")]
+fn gain(receiver: Option<&str>, body: &str) -> Result<&'static str, Custom<String>> {
+    let control: GainControl = serde_json::from_str(body)
+        .map_err(|e| Custom(Status::BadRequest, format!("invalid gain control: {e}")))?;
+    let sent = DECODER.lock().unwrap()
+        .as_ref()
+        .is_some_and(|handle| handle.command(receiver, SourceCommand::SetGain(control)));
+    if sent {
+        Ok("gain changed")
+    } else {
+        Err(Custom(Status::NotFound, "no such receiver running".into()))
+    }
+}
+
+/// Switch power to the antenna port on or off with a body of `on` or `off`. It can only be switched
+/// on for a HackRF started with `--antenna-power`.
+#[post("/antenna?<receiver>", data = "<body>")]
+fn antenna(receiver: Option<&str>, body: &str, config: &State<DecoderConfig>) -> Result<&'static str, Custom<String>> {
+    let on = match body.trim() {
+        "on" => true,
+        "off" => false,
+        _ => return Err(Custom(Status::BadRequest, "expected on or off".into())),
+    };
+    let allowed = matches!(config.get_source(receiver), Some(SourceConfig::HackRf { antenna_power: true, .. }));
+    if on && !allowed {
+        return Err(Custom(Status::Forbidden, "antenna power was not enabled with --antenna-power".into()));
+    }
+    let sent = DECODER.lock().unwrap()
+        .as_ref()
+        .is_some_and(|handle| handle.command(receiver, SourceCommand::SetAntennaPower(on)));
+    if sent {
+        Ok(if on { "antenna power on" } else { "antenna power off" })
+    } else {
+        Err(Custom(Status::NotFound, "no such receiver running".into()))
+    }
+}
+
+/// Move a channel, numbered from 0 in the order given, to the frequency in Hz in the body, e.g. to
+/// follow a transmitter that has drifted.
+#[post("/retune?<receiver>&<channel>", data = "<body>")]
+fn retune(receiver: Option<&str>, channel: usize, body: &str) -> Result<&'static str, Custom<String>> {
+    let frequency: f64 = body.trim().parse()
+        .map_err(|e| Custom(Status::BadRequest, format!("invalid frequency: {e}")))?;
+    let sent = match DECODER.lock().unwrap().as_ref() {
+        Some(handle) => handle.retune(receiver, channel, frequency)
+            .map_err(|e| Custom(Status::BadRequest, e.to_string()))?,
+        None => false,
+    };
+    if sent {
+        Ok("retuning")
+    } else {
+        Err(Custom(Status::NotFound, "no such channel running".into()))
+    }
+}
+
+#[post("/decoder/stop")]
+fn stop() -> &'static str {
+    if stop_decoding() {
+        "stopped"
+    } else {
+        "not running"
+    }
+}
+
 #[launch]
 fn rocket() -> _ {
-    let decoder_thread = thread::spawn(||{
-        telemega::start_decoders(|_freq, packet| {
-            // println!("{freq},{packet:?}");
-            println!("{}", packet.crc_match());
-            if let Ok(queued) = unsafe { QUEUE.get_mut() } {
-                queued.push(packet);
-            } else {
-                eprintln!("Failed to queue packet");
+    let args = Arguments::parse();
+    if let Some(command) = &args.command {
+        let result = match command {
+            Command::Transmit(transmit_args) => transmit(transmit_args),
+            Command::Scan(scan_args) => scan(scan_args),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed: {e}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if args.list_devices {
+        list_devices();
+        std::process::exit(0);
+    }
+    if args.cpld_checksum {
+        match HackRFIQSource::cpld_checksum(args.device.as_deref()) {
+            Ok(checksum) => println!("CPLD checksum 0x{checksum:08x}"),
+            Err(e) => {
+                eprintln!("Failed to read CPLD checksum: {e}");
+                std::process::exit(1);
             }
-        });
-    });
+        }
+        std::process::exit(0);
+    }
+    let config = DecoderConfig::from(args);
+    // keep serving the GUI even without a radio, decoding can be started once one is plugged in
+    match start_decoding(&config) {
+        Ok(handle) => *DECODER.lock().unwrap() = Some(handle),
+        Err(e) => eprintln!("Failed to start decoding: {e}"),
+    }
     let server = rocket::build()
-        .mount("/", routes![data])
+        .manage(config)
+        .attach(AdHoc::on_shutdown("Stop decoders", |_| Box::pin(async {
+            stop_decoding();
+        })))
+        .mount("/", routes![data, events, status, start, gain, antenna, retune, stop])
         .mount("/", FileServer::from(relative!("GUI/public")));
 
     // if webbrowser::open("http://127.0.0.1:8000").is_err() {
@@ -0,0 +1,82 @@
+//! A stand-in rtl_tcp server that serves a recording instead of a live RTL-SDR, for trying the
+//! rtl_tcp source without hardware.
+//!
+//! ```text
+//! cargo run -p telemega --example rtl_tcp_replay -- flight.cu8 2400000 127.0.0.1:1234
+//! cargo run --release -- 436750000 --rtl-tcp 127.0.0.1:1234 --center 436850000
+//! ```
+//!
+//! `.cs8` recordings are converted to unsigned samples on the fly, anything else is served as is.
+//! Commands from the client are printed but otherwise ignored.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHUNK: usize = 64 * 1024;
+
+fn print_commands(mut stream: TcpStream) {
+    let mut message = [0u8; 5];
+    while stream.read_exact(&mut message).is_ok() {
+        let param = u32::from_be_bytes([message[1], message[2], message[3], message[4]]);
+        println!("command {:#04x} {param}", message[0]);
+    }
+}
+
+fn serve(mut stream: TcpStream, path: &Path, sample_rate: f64) -> std::io::Result<()> {
+    let signed = path.extension().is_some_and(|ext| ext == "cs8");
+    let mut file = BufReader::new(File::open(path)?);
+
+    // an R820T with its 29 gain steps, which is what most dongles report
+    let mut header = Vec::from(*b"RTL0");
+    header.extend_from_slice(&5u32.to_be_bytes());
+    header.extend_from_slice(&29u32.to_be_bytes());
+    stream.write_all(&header)?;
+
+    let commands = stream.try_clone()?;
+    thread::spawn(move || print_commands(commands));
+
+    let start = Instant::now();
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let ct = file.read(&mut buf)?;
+        if ct == 0 {
+            println!("end of recording");
+            // also ends the command thread's half of the connection
+            return stream.shutdown(Shutdown::Both);
+        }
+        if signed {
+            buf[..ct].iter_mut().for_each(|b| *b ^= 0x80);
+        }
+        stream.write_all(&buf[..ct])?;
+
+        // keep to the recording's real time rate like a radio would
+        sent += ct as u64 / 2;
+        let due = Duration::from_secs_f64(sent as f64 / sample_rate);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("usage: rtl_tcp_replay <recording> [sample rate] [address]");
+    let sample_rate: f64 = args.next().map_or(2_400_000.0, |rate| rate.parse().expect("invalid sample rate"));
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:1234".into());
+
+    let listener = TcpListener::bind(&address)?;
+    println!("serving {path} on {address}");
+    for stream in listener.incoming() {
+        let stream = stream?;
+        println!("client connected from {}", stream.peer_addr()?);
+        if let Err(e) = serve(stream, Path::new(&path), sample_rate) {
+            println!("client disconnected: {e}");
+        }
+    }
+    Ok(())
+}
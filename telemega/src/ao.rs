@@ -1,6 +1,7 @@
 
 
 extern "C" {
+    fn ao_fec_encode(r#in: *const u8, len: u8, out: *mut u8) -> u8;
     // allegedly Option<extern fn> is a nullable ptr
     fn ao_fec_decode(r#in: *const u8, len: u16, out: *mut u8, out_len: u8, callback: Option<extern fn() -> u16>) -> u8;
 }
@@ -11,4 +12,17 @@ pub fn fec_decode(input: &[u8], out: &mut [u8]) {
     unsafe {
         ao_fec_decode(input.as_ptr(), input.len() as u16, out.as_mut_ptr(), out.len() as u8, None);
     }
-}
\ No newline at end of file
+}
+
+/// FEC encode, whiten and interleave a telemetry packet with its CRC appended, ready to transmit.
+///
+/// Only whole 32 byte packets are taken: `ao_fec_encode` pads odd lengths with an extra byte the
+/// output would have to make room for, and takes the length as a `u8`.
+pub fn fec_encode(input: &[u8; 32]) -> [u8; 72] {
+    // CRC and trellis terminator, doubled by the rate 1/2 code
+    let mut out = [0; (32 + 4) * 2];
+    unsafe {
+        ao_fec_encode(input.as_ptr(), input.len() as u8, out.as_mut_ptr());
+    }
+    out
+}
@@ -0,0 +1,168 @@
+//! Splits the wideband stream from the radio into a narrowband stream for each channel.
+//!
+//! An overlap-save filter bank: each block of input goes through one FFT shared by every channel,
+//! then each channel takes the bins around its frequency, applies the low-pass filter to them and
+//! turns just those back into samples with a small inverse FFT, which shifts it down to baseband
+//! and decimates it at the same time.
+
+use std::sync::Arc;
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use crate::filter::lowpass_taps;
+use crate::streaming_gfsk::FILTER_CUTOFF;
+
+/// Samples per bit the channels are decimated to, which is as few as the discriminator and bit
+/// slicer still work reliably with.
+const SAMPLES_PER_BIT: f64 = 10.0;
+/// Size of the inverse FFT for each channel, so the number of channel samples per block including
+/// the overlap.
+const CHANNEL_FFT_SIZE: usize = 256;
+/// Part of each block that overlaps the last, and so the length of the channel filter.
+const OVERLAP_FRACTION: usize = 4;
+/// Passband of the channel filter as a fraction of the channel sample rate. Only what would alias
+/// onto the channel itself needs to be stopped, as the decoder's own filter takes care of the rest.
+const CHANNEL_CUTOFF: f64 = 0.4;
+
+/// Decimation that takes `sample_rate` down to about [`SAMPLES_PER_BIT`] samples per bit.
+pub(crate) fn decimation_factor(sample_rate: f64, baud: f64) -> usize {
+    ((sample_rate / (baud * SAMPLES_PER_BIT)).floor() as usize).max(1)
+}
+
+/// How far from the center of samples at `sample_rate` a channel can be before part of it would
+/// alias onto a channel the other side.
+pub(crate) fn max_offset(sample_rate: f64) -> f64 {
+    (sample_rate / 2.0 - FILTER_CUTOFF).max(0.0)
+}
+
+/// How far a channel's decoder can move it from where it was split out at `channel_rate` and
+/// still have all of its own filter's passband inside the channel filter's.
+pub(crate) fn retune_range(channel_rate: f64) -> f64 {
+    (CHANNEL_CUTOFF * channel_rate - FILTER_CUTOFF).max(0.0)
+}
+
+struct ChannelBins {
+    /// FFT bin nearest the channel.
+    bin: usize,
+    /// Offset of the channel from the bin.
+    residual: f64,
+    /// Position of the current block's first sample in the period of the bin's frequency, to keep
+    /// the shift down continuous from one block to the next.
+    phase: usize,
+}
+
+pub(crate) struct Channelizer {
+    factor: usize,
+    // input samples per block and how many of them overlap the previous block
+    block: usize,
+    overlap: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    filter: Vec<Complex<f32>>,
+    channels: Vec<ChannelBins>,
+    // the overlap from the last block followed by samples not yet processed
+    input: Vec<Complex<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    narrow: Vec<Complex<f32>>,
+}
+
+impl Channelizer {
+    /// Extract the channels `offsets` Hz from the center of samples at `sample_rate`, each
+    /// decimated by `factor`.
+    pub(crate) fn new(sample_rate: f64, offsets: &[f64], factor: usize) -> Channelizer {
+        let block = CHANNEL_FFT_SIZE * factor;
+        let overlap = CHANNEL_FFT_SIZE / OVERLAP_FRACTION * factor;
+
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(block);
+        let mut filter: Vec<Complex<f32>> = lowpass_taps(overlap + 1, CHANNEL_CUTOFF / factor as f64)
+            .into_iter()
+            .map(|tap| Complex::new(tap, 0.0))
+            .collect();
+        filter.resize(block, Complex::new(0.0, 0.0));
+        fft.process(&mut filter);
+        // rustfft leaves the forward and inverse transforms unscaled
+        for tap in filter.iter_mut() {
+            *tap /= block as f32;
+        }
+
+        let bin_width = sample_rate / block as f64;
+        let channels = offsets.iter()
+            .map(|offset| {
+                let bin = (offset / bin_width).round();
+                ChannelBins {
+                    bin: (bin as i64).rem_euclid(block as i64) as usize,
+                    residual: offset - bin * bin_width,
+                    phase: 0,
+                }
+            })
+            .collect();
+
+        Channelizer {
+            factor,
+            block,
+            overlap,
+            fft,
+            ifft: planner.plan_fft_inverse(CHANNEL_FFT_SIZE),
+            filter,
+            channels,
+            input: vec![Complex::new(0.0, 0.0); overlap],
+            spectrum: vec![Complex::new(0.0, 0.0); block],
+            narrow: vec![Complex::new(0.0, 0.0); CHANNEL_FFT_SIZE],
+        }
+    }
+
+    /// Offset of each channel from the frequency it was shifted down by, which is left for its
+    /// decoder to take out.
+    pub(crate) fn residuals(&self) -> Vec<f64> {
+        self.channels.iter().map(|channel| channel.residual).collect()
+    }
+
+    /// Channelize `samples`, appending each channel's output to its entry in `out`.
+    pub(crate) fn process(&mut self, samples: &[Complex<f32>], out: &mut [Vec<Complex<f32>>]) {
+        self.input.extend_from_slice(samples);
+        while self.input.len() >= self.block {
+            self.process_block(out, CHANNEL_FFT_SIZE);
+            self.input.drain(..self.block - self.overlap);
+        }
+    }
+
+    /// Channelize whatever is left over once no more samples will come.
+    pub(crate) fn flush(&mut self, out: &mut [Vec<Complex<f32>>]) {
+        let pending = self.input.len() - self.overlap;
+        if pending >= self.factor {
+            self.input.resize(self.block, Complex::new(0.0, 0.0));
+            self.process_block(out, self.overlap / self.factor + pending / self.factor);
+        }
+        self.input.truncate(self.overlap);
+    }
+
+    /// Samples were lost, flush what is left and start again from silence.
+    pub(crate) fn reset(&mut self, out: &mut [Vec<Complex<f32>>]) {
+        self.flush(out);
+        self.input.fill(Complex::new(0.0, 0.0));
+    }
+
+    /// Filter the block at the start of `input`, keeping channel samples up to `end`.
+    fn process_block(&mut self, out: &mut [Vec<Complex<f32>>], end: usize) {
+        self.spectrum.copy_from_slice(&self.input[..self.block]);
+        self.fft.process(&mut self.spectrum);
+
+        let half = CHANNEL_FFT_SIZE / 2;
+        let step = self.block - self.overlap;
+        for (channel, out) in self.channels.iter_mut().zip(out.iter_mut()) {
+            // bins above the channel go at the start, those below it wrap around to the end
+            for (i, narrow) in self.narrow.iter_mut().enumerate() {
+                let offset = if i < half { i } else { self.block + i - CHANNEL_FFT_SIZE };
+                let bin = (channel.bin + offset) % self.block;
+                *narrow = self.spectrum[bin] * self.filter[offset];
+            }
+            self.ifft.process(&mut self.narrow);
+
+            // the inverse FFT shifts each block down as if it started at sample 0
+            let angle = -2.0 * std::f64::consts::PI * channel.phase as f64 / self.block as f64;
+            let rotation = Complex::from_polar(1.0, angle as f32);
+            out.extend(self.narrow[self.overlap / self.factor..end].iter().map(|s| s * rotation));
+            channel.phase = (channel.phase + channel.bin * step) % self.block;
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::gain::GainControl;
+use crate::recording_source::RawRecording;
+use crate::sample_format::SampleFormat;
+
+pub const DEFAULT_SAMPLE_RATE: f64 = 20_000_000.0;
+/// Rates the HackRF can be run at. Below 8 MS/s its ADC aliases badly, and the rates in between
+/// are left out as the decoder decimates each channel down to the same rate whatever it starts at.
+pub const HACKRF_SAMPLE_RATES: [f64; 5] = [8_000_000.0, 10_000_000.0, 12_500_000.0, 16_000_000.0, 20_000_000.0];
+pub const DEFAULT_BAUD: f64 = 38400.0;
+pub const DEFAULT_BUS_DEPTH: usize = 10000;
+
+/// Offset of the automatically chosen center above the highest channel, which keeps
+/// every channel clear of the HackRF's DC spike.
+const CENTER_OFFSET: f64 = 100_000.0;
+
+/// A frequency to decode, with an optional human readable name such as "Sustainer AL0".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub frequency: f64,
+    pub label: Option<String>,
+}
+
+impl Channel {
+    pub fn new(frequency: f64) -> Channel {
+        Channel { frequency, label: None }
+    }
+
+    pub fn labelled(frequency: f64, label: impl Into<String>) -> Channel {
+        Channel { frequency, label: Some(label.into()) }
+    }
+}
+
+/// Where the decoder pipeline gets its IQ samples from.
+#[derive(Clone, Debug)]
+pub enum SourceConfig {
+    /// The HackRF One with the given serial number (see [`async_libhackrf::list_devices`]), or the
+    /// first one not in use by another program, with its gain set by `gain`.
+    HackRf {
+        serial: Option<String>,
+        gain: GainControl,
+        /// Power the antenna port for a mast mounted LNA, see
+        /// [`HackRFIQSource::enable_antenna_power`](crate::HackRFIQSource::enable_antenna_power).
+        /// Without this it cannot be switched on while running either.
+        antenna_power: bool,
+    },
+    /// A recording of interleaved IQ samples, or a WAV file with I and Q as its two channels.
+    ///
+    /// SigMF recordings are read using their metadata. Otherwise without a format it is guessed from
+    /// the file extension (see [`SampleFormat::from_extension`]) and failing that assumed to be a
+    /// `hackrf_transfer` capture.
+    ///
+    /// A path of `-` reads from stdin, and named pipes are read the same way, so samples can be
+    /// piped in from other SDR tools.
+    File {
+        path: PathBuf,
+        format: Option<SampleFormat>,
+    },
+    /// A remote RTL-SDR served by `rtl_tcp` at `address` (host:port), using the tuner's AGC
+    /// unless given a gain in dB.
+    RtlTcp {
+        address: String,
+        gain: Option<f64>,
+    },
+}
+
+impl Default for SourceConfig {
+    fn default() -> SourceConfig {
+        SourceConfig::HackRf { serial: None, gain: GainControl::default(), antenna_power: false }
+    }
+}
+
+impl SourceConfig {
+    pub fn hackrf(serial: impl Into<String>) -> SourceConfig {
+        SourceConfig::HackRf { serial: Some(serial.into()), gain: GainControl::default(), antenna_power: false }
+    }
+
+    pub fn file(path: impl Into<PathBuf>) -> SourceConfig {
+        SourceConfig::File { path: path.into(), format: None }
+    }
+}
+
+/// Center just above the highest channel.
+fn center_above(channels: &[Channel]) -> f64 {
+    channels.iter()
+        .map(|channel| channel.frequency)
+        .max_by(|a, b| a.total_cmp(b))
+        .map_or(0.0, |max| max + CENTER_OFFSET)
+}
+
+/// A further radio decoded alongside the main one, e.g. a second receive site or a HackRF
+/// covering another part of the band. Packets it hears are tagged with its name.
+#[derive(Clone, Debug)]
+pub struct ReceiverConfig {
+    pub(crate) name: String,
+    pub(crate) source: SourceConfig,
+    pub(crate) channels: Vec<Channel>,
+    pub(crate) center: Option<f64>,
+    pub(crate) sample_rate: Option<f64>,
+}
+
+impl ReceiverConfig {
+    pub fn new(name: impl Into<String>, source: SourceConfig) -> ReceiverConfig {
+        ReceiverConfig {
+            name: name.into(),
+            source,
+            channels: Vec::new(),
+            center: None,
+            sample_rate: None,
+        }
+    }
+
+    pub fn channel(mut self, channel: Channel) -> ReceiverConfig {
+        self.channels.push(channel);
+        self
+    }
+
+    pub fn center(mut self, hz: f64) -> ReceiverConfig {
+        self.center = Some(hz);
+        self
+    }
+
+    pub fn sample_rate(mut self, hz: f64) -> ReceiverConfig {
+        self.sample_rate = Some(hz);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    pub fn get_center(&self) -> f64 {
+        self.center.unwrap_or_else(|| center_above(&self.channels))
+    }
+}
+
+/// Everything `start_decoders` needs to know to run, so that programs embedding the
+/// crate can configure the pipeline without going through the command line.
+///
+/// ```no_run
+/// use telemega::{Channel, DecoderConfig, SourceConfig};
+///
+/// let config = DecoderConfig::new(vec![436_750_000.0, 436_350_000.0])
+///     .channel(Channel::labelled(436_550_000.0, "Booster AL3"))
+///     .source(SourceConfig::file("flight.cs8"))
+///     .center(436_850_000.0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DecoderConfig {
+    pub(crate) channels: Vec<Channel>,
+    pub(crate) center: Option<f64>,
+    pub(crate) source: SourceConfig,
+    pub(crate) sample_rate: Option<f64>,
+    pub(crate) baud: f64,
+    pub(crate) bus_depth: usize,
+    pub(crate) record: Option<PathBuf>,
+    pub(crate) raw_recording: Option<RawRecording>,
+    pub(crate) playback_speed: Option<f64>,
+    pub(crate) start_offset: Duration,
+    pub(crate) receivers: Vec<ReceiverConfig>,
+}
+
+impl DecoderConfig {
+    pub fn new(frequencies: Vec<f64>) -> DecoderConfig {
+        DecoderConfig {
+            channels: frequencies.into_iter().map(Channel::new).collect(),
+            center: None,
+            source: SourceConfig::default(),
+            sample_rate: None,
+            baud: DEFAULT_BAUD,
+            bus_depth: DEFAULT_BUS_DEPTH,
+            record: None,
+            raw_recording: None,
+            playback_speed: None,
+            start_offset: Duration::ZERO,
+            receivers: Vec::new(),
+        }
+    }
+
+    pub fn channel(mut self, channel: Channel) -> DecoderConfig {
+        self.channels.push(channel);
+        self
+    }
+
+    /// Tune to `hz` instead of picking a center just above the highest channel.
+    pub fn center(mut self, hz: f64) -> DecoderConfig {
+        self.center = Some(hz);
+        self
+    }
+
+    pub fn source(mut self, source: SourceConfig) -> DecoderConfig {
+        self.source = source;
+        self
+    }
+
+    /// Rate to run the radio at, or the rate of a raw recording. WAV files carry their own rate,
+    /// which this overrides.
+    pub fn sample_rate(mut self, hz: f64) -> DecoderConfig {
+        self.sample_rate = Some(hz);
+        self
+    }
+
+    pub fn baud(mut self, baud: f64) -> DecoderConfig {
+        self.baud = baud;
+        self
+    }
+
+    /// Number of sample buffers that may be queued for the slowest channel before
+    /// the source blocks.
+    pub fn bus_depth(mut self, depth: usize) -> DecoderConfig {
+        self.bus_depth = depth;
+        self
+    }
+
+    /// Save the received samples as a SigMF recording at `path`, annotated with every decoded packet.
+    pub fn record(mut self, path: impl Into<PathBuf>) -> DecoderConfig {
+        self.record = Some(path.into());
+        self
+    }
+
+    /// Keep a copy of the raw samples in rotating cs8 files, without ever slowing down decoding.
+    pub fn raw_recording(mut self, recording: RawRecording) -> DecoderConfig {
+        self.raw_recording = Some(recording);
+        self
+    }
+
+    /// Play a file source back in real time multiplied by `speed` instead of as fast as it can be
+    /// read, e.g. 0.5 for half speed or 10.0 to skip through a long wait on the pad.
+    pub fn playback_speed(mut self, speed: f64) -> DecoderConfig {
+        self.playback_speed = Some(speed);
+        self
+    }
+
+    /// Skip this far into a file source before decoding.
+    pub fn start_offset(mut self, offset: Duration) -> DecoderConfig {
+        self.start_offset = offset;
+        self
+    }
+
+    /// Decode another radio at the same time. Packets heard by more than one receiver are only
+    /// reported once, by whichever decoded them first.
+    ///
+    /// Playback speed and start offset apply to every file source, recording only to the main one.
+    pub fn receiver(mut self, receiver: ReceiverConfig) -> DecoderConfig {
+        self.receivers.push(receiver);
+        self
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    pub fn receivers(&self) -> &[ReceiverConfig] {
+        &self.receivers
+    }
+
+    /// Where the named receiver's samples come from, or the main one's for `None`.
+    pub fn get_source(&self, receiver: Option<&str>) -> Option<&SourceConfig> {
+        match receiver {
+            None => Some(&self.source),
+            Some(name) => self.receivers.iter()
+                .find(|r| r.name == name)
+                .map(|r| &r.source),
+        }
+    }
+
+    pub fn get_center(&self) -> f64 {
+        self.center.unwrap_or_else(|| center_above(&self.channels))
+    }
+}
@@ -0,0 +1,35 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::DecodedPacket;
+
+/// How long after a packet is first decoded another receiver's copy of it is ignored. Long
+/// enough to cover one receiver's decoders lagging behind another's, short enough that the
+/// flight computer's tick cannot have wrapped around to the same value.
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Drops packets that have already been decoded by another receiver.
+pub(crate) struct Deduplicator {
+    seen: VecDeque<(Instant, (u16, u16, u8))>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Deduplicator {
+        Deduplicator { seen: VecDeque::new() }
+    }
+
+    /// Whether this is the first copy of `packet`, identified by serial, tick and type.
+    pub fn is_new(&mut self, packet: &DecodedPacket) -> bool {
+        let now = Instant::now();
+        while self.seen.front().is_some_and(|(at, _)| now.duration_since(*at) > WINDOW) {
+            self.seen.pop_front();
+        }
+
+        let key = (packet.serial(), (packet.tick() * 100.0).round() as u16, packet.ptype());
+        if self.seen.iter().any(|(_, seen)| *seen == key) {
+            false
+        } else {
+            self.seen.push_back((now, key));
+            true
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Filters designed at run time for whatever sample rate the decoder is working at.
+
+use std::f64::consts::PI;
+use num_complex::Complex;
+
+/// One second order section: `[b0, b1, b2, a0, a1, a2]` with `a0` always 1, as from scipy's
+/// `butter(..., output='sos')`.
+pub(crate) type Section = [f32; 6];
+
+/// A Butterworth low-pass filter of `order` with its -3 dB point at `cutoff` Hz, for samples at
+/// `sample_rate`.
+///
+/// Each section has unity gain at DC, rather than the whole gain being in the first as scipy does,
+/// which keeps the coefficients in range of an `f32` at high sample rates.
+pub(crate) fn butterworth_lowpass(order: usize, cutoff: f64, sample_rate: f64) -> Vec<Section> {
+    // prewarp so that the bilinear transform puts the cutoff in the right place
+    let warped = 2.0 * sample_rate * (PI * cutoff / sample_rate).tan();
+    let to_z = |pole: Complex<f64>| (2.0 * sample_rate + pole) / (2.0 * sample_rate - pole);
+
+    let mut sections = Vec::new();
+    // the analog poles are spread evenly around the left half of a circle; with an odd order one of
+    // them is on the real axis and makes a first order section
+    if order % 2 == 1 {
+        let z = to_z(Complex::new(-warped, 0.0)).re;
+        let gain = (1.0 - z) / 2.0;
+        sections.push([gain as f32, gain as f32, 0.0, 1.0, -z as f32, 0.0]);
+    }
+    // from the pole nearest the real axis out, each pole with its conjugate
+    for k in (0..order / 2).rev() {
+        let angle = PI * (2 * k + order + 1) as f64 / (2 * order) as f64;
+        let z = to_z(Complex::from_polar(warped, angle));
+        let (a1, a2) = (-2.0 * z.re, z.norm_sqr());
+        let gain = (1.0 + a1 + a2) / 4.0;
+        sections.push([gain as f32, 2.0 * gain as f32, gain as f32, 1.0, a1 as f32, a2 as f32]);
+    }
+    sections
+}
+
+/// Filter `x` in place through `sos`, carrying the state of each section in `zi` from one call
+/// to the next.
+pub(crate) fn sosfilt(sos: &[Section], x: &mut [Complex<f32>], zi: &mut [[Complex<f32>; 2]]) {
+    for x in x.iter_mut() {
+        let mut x_c = *x;
+        for (section, zi) in sos.iter().zip(zi.iter_mut()) {
+            let x_n = section[0] * x_c + zi[0];
+            zi[0] = section[1] * x_c - section[4] * x_n + zi[1];
+            zi[1] = section[2] * x_c - section[5] * x_n;
+            x_c = x_n;
+        }
+        *x = x_c;
+    }
+}
+
+/// Taps of a windowed sinc (Hamming) low-pass filter `len` long with its cutoff at `cutoff` times
+/// the sample rate, normalized to unity gain at DC.
+pub(crate) fn lowpass_taps(len: usize, cutoff: f64) -> Vec<f32> {
+    let middle = (len - 1) as f64 / 2.0;
+    let taps: Vec<f64> = (0..len)
+        .map(|i| {
+            let t = i as f64 - middle;
+            let sinc = if t == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * t).sin() / (PI * t) };
+            sinc * (0.54 - 0.46 * (2.0 * PI * i as f64 / (len - 1) as f64).cos())
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    taps.iter().map(|tap| (tap / sum) as f32).collect()
+}
@@ -0,0 +1,466 @@
+//! Choosing the HackRF's gain, either fixed or from the level of the samples being received.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use num_complex::Complex;
+use serde::{Deserialize, Serialize};
+
+/// HackRF front end gain stages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GainSettings {
+    /// LNA (IF) gain in dB.
+    pub lna: u16,
+    /// VGA (baseband) gain in dB.
+    pub vga: u16,
+    /// Whether the 14 dB RF amplifier is on.
+    pub amp: bool,
+}
+
+const MAX_GAIN_INDEX: i16 = 36;
+/// Highest LNA gain and the steps it is set in, the radio rounds anything in between down.
+const MAX_LNA_GAIN: u16 = 40;
+const LNA_GAIN_STEP: u16 = 8;
+const MAX_VGA_GAIN: u16 = 62;
+const VGA_GAIN_STEP: u16 = 2;
+const START_GAIN_INDEX: i16 = 6;
+const FULL_SCALE_POWER: f64 = 128.0 * 128.0;
+
+/// The automatic gain controls step through the LNA in 8 dB steps before the VGA in 2 dB steps,
+/// which keeps noise down by amplifying as early as possible.
+fn gain_index(idx: i16, amp: bool) -> GainSettings {
+    let (lna, vga) = if idx < 0 {
+        (0, 0)
+    } else if idx <= 5 {
+        (idx * 8, 0)
+    } else if idx <= 5 + 31 {
+        (40, (idx - 5) * 2)
+    } else {
+        (40, 62)
+    };
+    GainSettings { lna: lna as u16, vga: vga as u16, amp }
+}
+
+/// The gain index with the total gain closest to `settings`, for carrying on from a manual gain.
+fn index_of(settings: GainSettings) -> i16 {
+    let total = |settings: GainSettings| settings.lna as i32 + settings.vga as i32;
+    (0..=MAX_GAIN_INDEX)
+        .min_by_key(|&idx| (total(gain_index(idx, settings.amp)) - total(settings)).abs())
+        .unwrap_or(START_GAIN_INDEX)
+}
+
+/// Check manual gains are in range and round them down to steps the radio can do, so that what is
+/// reported is what the radio is set to.
+fn manual_gain(lna: u16, vga: u16, amp: bool) -> Result<GainControl, String> {
+    if lna > MAX_LNA_GAIN {
+        return Err(format!("LNA gain {lna} dB is over {MAX_LNA_GAIN} dB"));
+    }
+    if vga > MAX_VGA_GAIN {
+        return Err(format!("VGA gain {vga} dB is over {MAX_VGA_GAIN} dB"));
+    }
+    Ok(GainControl::Manual {
+        lna: lna / LNA_GAIN_STEP * LNA_GAIN_STEP,
+        vga: vga / VGA_GAIN_STEP * VGA_GAIN_STEP,
+        amp,
+    })
+}
+
+/// Check a window for the automatic gain controls is at least a millisecond, so that it covers
+/// some samples.
+fn agc_window(window_ms: f64) -> Result<u64, String> {
+    if window_ms.is_nan() || window_ms < 1.0 {
+        return Err(format!("AGC window {window_ms} ms is under 1 ms"));
+    }
+    Ok(window_ms as u64)
+}
+
+/// Check the peak AGC has a band to settle in.
+fn peak_agc(low: f32, high: f32, window_ms: f64, amp: bool) -> Result<GainControl, String> {
+    if low.is_nan() || high.is_nan() || low >= high {
+        return Err(format!("peak AGC low {low} is not below high {high}"));
+    }
+    Ok(GainControl::Peak { low, high, window_ms: agc_window(window_ms)?, amp })
+}
+
+/// Check the RMS AGC has a band to settle in.
+fn rms_agc(target_dbfs: f32, hysteresis_db: f32, window_ms: f64, amp: bool) -> Result<GainControl, String> {
+    if hysteresis_db.is_nan() || hysteresis_db < 0.0 {
+        return Err(format!("RMS AGC hysteresis {hysteresis_db} dB is negative"));
+    }
+    Ok(GainControl::Rms { target_dbfs, hysteresis_db, window_ms: agc_window(window_ms)?, amp })
+}
+
+/// How the gain of a HackRF is set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase", try_from = "GainControlFields")]
+pub enum GainControl {
+    /// Fixed gains in dB, LNA up to 40 in steps of 8 and VGA up to 62 in steps of 2.
+    Manual { lna: u16, vga: u16, amp: bool },
+    /// Turn the gain up a step when the largest sample over a window is below `low`, and down a
+    /// step when it is above `high`, on the ±128 scale of a HackRF sample.
+    Peak { low: f32, high: f32, window_ms: u64, amp: bool },
+    /// Turn the gain up or down a step when the RMS level over a window is more than
+    /// `hysteresis_db` away from `target_dbfs`. Less jumpy than [`GainControl::Peak`] when there
+    /// are occasional strong bursts from other transmitters.
+    Rms { target_dbfs: f32, hysteresis_db: f32, window_ms: u64, amp: bool },
+}
+
+/// [`GainControl`] as sent, before the gains and AGC settings are checked.
+#[derive(Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+enum GainControlFields {
+    Manual { lna: u16, vga: u16, amp: bool },
+    Peak { low: f32, high: f32, window_ms: u64, amp: bool },
+    Rms { target_dbfs: f32, hysteresis_db: f32, window_ms: u64, amp: bool },
+}
+
+impl TryFrom<GainControlFields> for GainControl {
+    type Error = String;
+
+    fn try_from(fields: GainControlFields) -> Result<GainControl, String> {
+        match fields {
+            GainControlFields::Manual { lna, vga, amp } => manual_gain(lna, vga, amp),
+            GainControlFields::Peak { low, high, window_ms, amp } => peak_agc(low, high, window_ms as f64, amp),
+            GainControlFields::Rms { target_dbfs, hysteresis_db, window_ms, amp } => {
+                rms_agc(target_dbfs, hysteresis_db, window_ms as f64, amp)
+            }
+        }
+    }
+}
+
+impl Default for GainControl {
+    fn default() -> GainControl {
+        GainControl::Peak { low: 40.0, high: 90.0, window_ms: 1000, amp: true }
+    }
+}
+
+impl GainControl {
+    /// The same control with the RF amplifier switched on or off.
+    pub fn amp(mut self, on: bool) -> GainControl {
+        match &mut self {
+            GainControl::Manual { amp, .. } | GainControl::Peak { amp, .. } | GainControl::Rms { amp, .. } => *amp = on,
+        }
+        self
+    }
+
+    /// Gain to start receiving with before anything has been measured. Manual gains out of range
+    /// are limited to the most the radio can do.
+    pub fn initial(&self) -> GainSettings {
+        match *self {
+            GainControl::Manual { lna, vga, amp } => GainSettings {
+                lna: lna.min(MAX_LNA_GAIN) / LNA_GAIN_STEP * LNA_GAIN_STEP,
+                vga: vga.min(MAX_VGA_GAIN) / VGA_GAIN_STEP * VGA_GAIN_STEP,
+                amp,
+            },
+            GainControl::Peak { amp, .. } | GainControl::Rms { amp, .. } => gain_index(START_GAIN_INDEX, amp),
+        }
+    }
+
+    /// Create a controller for samples at `sample_rate`, carrying on from the `current` gain.
+    pub fn controller(&self, sample_rate: f64, current: GainSettings) -> Box<dyn GainController + Send> {
+        let window = |ms: u64| (sample_rate * ms as f64 / 1000.0) as u64;
+        match *self {
+            GainControl::Manual { .. } => Box::new(ManualGain(self.initial())),
+            GainControl::Peak { low, high, window_ms, amp } => Box::new(PeakAgc {
+                low,
+                high,
+                window: window(window_ms),
+                amp,
+                index: index_of(current),
+                peak: 0.0,
+                seen: 0,
+            }),
+            GainControl::Rms { target_dbfs, hysteresis_db, window_ms, amp } => Box::new(RmsAgc {
+                target_dbfs,
+                hysteresis_db,
+                window: window(window_ms),
+                amp,
+                index: index_of(current),
+                power: 0.0,
+                seen: 0,
+            }),
+        }
+    }
+}
+
+fn parse_args<const N: usize>(args: &str, defaults: [f64; N]) -> Result<[f64; N], String> {
+    let mut values = defaults;
+    for (i, arg) in args.split(',').filter(|arg| !arg.is_empty()).enumerate() {
+        let value = values.get_mut(i).ok_or_else(|| format!("too many gain settings in {args}"))?;
+        *value = arg.parse().map_err(|e| format!("invalid gain setting {arg}: {e}"))?;
+    }
+    Ok(values)
+}
+
+/// Parses `manual:LNA,VGA`, `peak[:LOW,HIGH[,WINDOW_MS]]` or
+/// `rms[:TARGET_DBFS[,HYSTERESIS_DB[,WINDOW_MS]]]`, with the amplifier on.
+impl FromStr for GainControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GainControl, String> {
+        let (mode, args) = s.split_once(':').unwrap_or((s, ""));
+        match mode.to_ascii_lowercase().as_str() {
+            "manual" => {
+                if args.is_empty() {
+                    return Err("manual gain needs LNA,VGA in dB".into());
+                }
+                let [lna, vga] = parse_args(args, [0.0, 0.0])?;
+                if lna < 0.0 || vga < 0.0 {
+                    return Err(format!("negative gain in {args}"));
+                }
+                manual_gain(lna.min(u16::MAX as f64) as u16, vga.min(u16::MAX as f64) as u16, true)
+            }
+            "peak" => {
+                let [low, high, window_ms] = parse_args(args, [40.0, 90.0, 1000.0])?;
+                peak_agc(low as f32, high as f32, window_ms, true)
+            }
+            "rms" => {
+                let [target, hysteresis, window_ms] = parse_args(args, [-25.0, 3.0, 1000.0])?;
+                rms_agc(target as f32, hysteresis as f32, window_ms, true)
+            }
+            _ => Err(format!("unknown gain control {mode}, expected manual, peak or rms")),
+        }
+    }
+}
+
+impl Display for GainControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GainControl::Manual { lna, vga, amp } => write!(f, "manual LNA {lna} dB VGA {vga} dB amp {amp}"),
+            GainControl::Peak { low, high, window_ms, amp } => {
+                write!(f, "peak AGC {low}-{high} over {window_ms} ms amp {amp}")
+            }
+            GainControl::Rms { target_dbfs, hysteresis_db, window_ms, amp } => {
+                write!(f, "RMS AGC {target_dbfs}±{hysteresis_db} dBFS over {window_ms} ms amp {amp}")
+            }
+        }
+    }
+}
+
+/// Decides the gain from the samples received with the current one.
+///
+/// Implement this to try out another AGC with [`HackRFIQSource::set_gain_controller`](crate::HackRFIQSource::set_gain_controller).
+pub trait GainController {
+    /// Look at the next buffer of samples, returning new settings if the gain should change.
+    fn update(&mut self, samples: &[Complex<f32>], current: GainSettings) -> Option<GainSettings>;
+}
+
+struct ManualGain(GainSettings);
+
+impl GainController for ManualGain {
+    fn update(&mut self, _samples: &[Complex<f32>], current: GainSettings) -> Option<GainSettings> {
+        (current != self.0).then_some(self.0)
+    }
+}
+
+/// Step `index` up or down, returning the new settings if that changed anything.
+fn step(index: &mut i16, up: Option<bool>, amp: bool, current: GainSettings) -> Option<GainSettings> {
+    match up {
+        Some(true) => *index = i16::min(*index + 1, MAX_GAIN_INDEX),
+        Some(false) => *index = i16::max(*index - 1, 0),
+        None => {}
+    }
+    let settings = gain_index(*index, amp);
+    (settings != current).then_some(settings)
+}
+
+struct PeakAgc {
+    low: f32,
+    high: f32,
+    window: u64,
+    amp: bool,
+    index: i16,
+    peak: f32,
+    seen: u64,
+}
+
+impl GainController for PeakAgc {
+    fn update(&mut self, samples: &[Complex<f32>], current: GainSettings) -> Option<GainSettings> {
+        self.peak = samples.iter()
+            .map(|s| f32::max(s.re.abs(), s.im.abs()))
+            .fold(self.peak, f32::max);
+        self.seen += samples.len() as u64;
+        if self.seen < self.window {
+            return None;
+        }
+
+        let up = if self.peak < self.low {
+            Some(true)
+        } else if self.peak > self.high {
+            Some(false)
+        } else {
+            None
+        };
+        self.peak = 0.0;
+        self.seen = 0;
+        step(&mut self.index, up, self.amp, current)
+    }
+}
+
+struct RmsAgc {
+    target_dbfs: f32,
+    hysteresis_db: f32,
+    window: u64,
+    amp: bool,
+    index: i16,
+    power: f64,
+    seen: u64,
+}
+
+impl GainController for RmsAgc {
+    fn update(&mut self, samples: &[Complex<f32>], current: GainSettings) -> Option<GainSettings> {
+        self.power += samples.iter().map(|s| s.norm_sqr() as f64).sum::<f64>();
+        self.seen += samples.len() as u64;
+        if self.seen < self.window {
+            return None;
+        }
+
+        let level = (10.0 * (self.power / self.seen as f64 / FULL_SCALE_POWER).log10()) as f32;
+        let up = if level < self.target_dbfs - self.hysteresis_db {
+            Some(true)
+        } else if level > self.target_dbfs + self.hysteresis_db {
+            Some(false)
+        } else {
+            None
+        };
+        self.power = 0.0;
+        self.seen = 0;
+        step(&mut self.index, up, self.amp, current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manual(lna: u16, vga: u16) -> GainControl {
+        GainControl::Manual { lna, vga, amp: true }
+    }
+
+    #[test]
+    fn parses_manual_gain() {
+        assert_eq!("manual:32,20".parse(), Ok(manual(32, 20)));
+        // rounded down to what the radio can do
+        assert_eq!("manual:20,21".parse(), Ok(manual(16, 20)));
+        assert!("manual:48,0".parse::<GainControl>().is_err());
+        assert!("manual:0,64".parse::<GainControl>().is_err());
+        assert!("manual:-8,0".parse::<GainControl>().is_err());
+        assert!("manual".parse::<GainControl>().is_err());
+    }
+
+    #[test]
+    fn parses_agc() {
+        assert_eq!("peak".parse(), Ok(GainControl::default()));
+        assert_eq!(
+            "rms:-20,2".parse(),
+            Ok(GainControl::Rms { target_dbfs: -20.0, hysteresis_db: 2.0, window_ms: 1000, amp: true }),
+        );
+        assert!("loud".parse::<GainControl>().is_err());
+    }
+
+    #[test]
+    fn rejects_agc_without_a_band() {
+        assert!("peak:90,40".parse::<GainControl>().is_err());
+        assert!("peak:40,40".parse::<GainControl>().is_err());
+        assert!("rms:-20,-1".parse::<GainControl>().is_err());
+        // a hysteresis of 0 still settles, just more jumpily
+        assert!("rms:-20,0".parse::<GainControl>().is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_agc_window() {
+        assert!("peak:40,90,0".parse::<GainControl>().is_err());
+        assert!("peak:40,90,-1000".parse::<GainControl>().is_err());
+        assert!("rms:-20,3,0".parse::<GainControl>().is_err());
+        assert!("rms:-20,3,-1000".parse::<GainControl>().is_err());
+    }
+
+    #[test]
+    fn deserializes_agc_through_the_same_checks() {
+        let gain = |json: &str| serde_json::from_str::<GainControl>(json);
+        assert!(gain(r#"{"mode": "peak", "low": 90, "high": 40, "window_ms": 1000, "amp": true}"#).is_err());
+        assert!(gain(r#"{"mode": "peak", "low": 40, "high": 90, "window_ms": 0, "amp": true}"#).is_err());
+        assert!(gain(r#"{"mode": "rms", "target_dbfs": -20, "hysteresis_db": -1, "window_ms": 1000, "amp": true}"#).is_err());
+        assert!(gain(r#"{"mode": "rms", "target_dbfs": -20, "hysteresis_db": 3, "window_ms": 0, "amp": true}"#).is_err());
+        assert_eq!(
+            gain(r#"{"mode": "rms", "target_dbfs": -20, "hysteresis_db": 3, "window_ms": 500, "amp": false}"#).unwrap(),
+            GainControl::Rms { target_dbfs: -20.0, hysteresis_db: 3.0, window_ms: 500, amp: false },
+        );
+    }
+
+    #[test]
+    fn deserializes_manual_gain() {
+        let gain = |json: &str| serde_json::from_str::<GainControl>(json);
+        assert_eq!(gain(r#"{"mode": "manual", "lna": 20, "vga": 21, "amp": false}"#).unwrap(),
+            GainControl::Manual { lna: 16, vga: 20, amp: false });
+        assert!(gain(r#"{"mode": "manual", "lna": 48, "vga": 0, "amp": false}"#).is_err());
+        assert!(gain(r#"{"mode": "manual", "lna": 0, "vga": 63, "amp": false}"#).is_err());
+        assert_eq!(gain(r#"{"mode": "peak", "low": 40, "high": 90, "window_ms": 1000, "amp": true}"#).unwrap(),
+            GainControl::default());
+    }
+
+    #[test]
+    fn initial_manual_gain_is_what_the_radio_can_do() {
+        assert_eq!(manual(20, 21).initial(), GainSettings { lna: 16, vga: 20, amp: true });
+        assert_eq!(manual(48, 70).initial(), GainSettings { lna: 40, vga: 62, amp: true });
+    }
+
+    #[test]
+    fn manual_gain_settles() {
+        let control = manual(20, 21);
+        let mut controller = control.controller(1e6, GainSettings { lna: 0, vga: 0, amp: true });
+        let set = controller.update(&[], GainSettings { lna: 0, vga: 0, amp: true }).unwrap();
+        assert_eq!(set, control.initial());
+        assert_eq!(controller.update(&[], set), None);
+    }
+
+    #[test]
+    fn agc_carries_on_from_nearest_total_gain() {
+        let settings = |lna, vga| GainSettings { lna, vga, amp: true };
+        // 36 dB is as close to 32 as to 40, and the lower is safer
+        assert_eq!(gain_index(index_of(settings(16, 20)), true), settings(32, 0));
+        assert_eq!(gain_index(index_of(settings(16, 22)), true), settings(40, 0));
+        assert_eq!(gain_index(index_of(settings(40, 20)), true), settings(40, 20));
+        assert_eq!(gain_index(index_of(settings(24, 0)), true), settings(24, 0));
+        assert_eq!(gain_index(index_of(settings(40, 62)), true), settings(40, 62));
+    }
+
+    #[test]
+    fn peak_agc_steps() {
+        let strong = vec![Complex::new(120.0, 0.0); 100];
+        let weak = vec![Complex::new(10.0, 0.0); 100];
+        let control = GainControl::Peak { low: 40.0, high: 90.0, window_ms: 1, amp: true };
+        let start = control.initial();
+        let mut controller = control.controller(100_000.0, start);
+
+        let down = controller.update(&strong, start).unwrap();
+        assert_eq!((down.lna, down.vga), (40, 0));
+        let down = controller.update(&strong, down).unwrap();
+        assert_eq!((down.lna, down.vga), (32, 0));
+        let up = controller.update(&weak, down).unwrap();
+        assert_eq!((up.lna, up.vga), (40, 0));
+    }
+
+    #[test]
+    fn peak_agc_waits_for_window() {
+        let strong = vec![Complex::new(120.0, 0.0); 50];
+        let control = GainControl::Peak { low: 40.0, high: 90.0, window_ms: 1, amp: true };
+        let start = control.initial();
+        let mut controller = control.controller(100_000.0, start);
+        assert_eq!(controller.update(&strong, start), None);
+        assert!(controller.update(&strong, start).is_some());
+    }
+
+    #[test]
+    fn rms_agc_steps() {
+        let control = GainControl::Rms { target_dbfs: -20.0, hysteresis_db: 3.0, window_ms: 1, amp: true };
+        let start = control.initial();
+        let mut controller = control.controller(100_000.0, start);
+        // -6 dBFS
+        let loud = vec![Complex::new(64.0, 0.0); 100];
+        let down = controller.update(&loud, start).unwrap();
+        assert_eq!((down.lna, down.vga), (40, 0));
+        // about -20 dBFS
+        let right = vec![Complex::new(12.8, 0.0); 100];
+        assert_eq!(controller.update(&right, down), None);
+    }
+}
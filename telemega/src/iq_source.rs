@@ -1,142 +1,960 @@
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::fmt::{Display, Formatter};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use itertools::Itertools;
-use async_libhackrf::{HackRfOne, RxMode};
+use async_libhackrf::{HackRfOne, RxConfig, RxMode, UnknownMode};
+use nusb::transfer::TransferError;
 use num_complex::Complex;
+use serde::Serialize;
+use crate::config::DEFAULT_SAMPLE_RATE;
+use crate::gain::{GainControl, GainController, GainSettings};
+use crate::sample_format::SampleFormat;
+use crate::{sigmf, wav};
 
 
+/// Why an [`IQSource`] could not produce any more samples.
+#[derive(Debug)]
+pub enum IQError {
+    /// The end of a recording or a closed pipe, the source has nothing more to give.
+    Eof,
+    /// No radio could be found to open.
+    NotFound,
+    /// The radio was unplugged or the server went away.
+    Disconnected,
+    /// Samples were dropped because they were not read quickly enough.
+    Overflow,
+    /// No samples arrived in time, though more may still come.
+    Timeout,
+    /// The HackRF rejected a request or a USB transfer failed.
+    Device(async_libhackrf::Error),
+    /// The source cannot carry out a [`SourceCommand`].
+    Unsupported,
+    /// Antenna power was asked for without being allowed, see
+    /// [`HackRFIQSource::enable_antenna_power`].
+    AntennaPowerNotAllowed,
+    /// The HackRF was asked for a rate not in [`HACKRF_SAMPLE_RATES`](crate::HACKRF_SAMPLE_RATES).
+    SampleRate(f64),
+    /// A recording was asked to be played back at a speed that is not positive.
+    PlaybackSpeed(f64),
+    /// A receiver was configured without any frequencies to decode. Holds its name, `None` for the
+    /// main one.
+    NoChannels(Option<String>),
+    /// A channel in Hz is too far from its receiver's center frequency to be decoded at its sample
+    /// rate, or to be retuned to.
+    ChannelOutOfRange(f64),
+    Io(std::io::Error),
+}
+
+impl IQError {
+    /// Whether reading again might succeed, as opposed to the source being finished or broken.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, IQError::Overflow | IQError::Timeout)
+    }
+}
+
+impl Display for IQError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IQError::Eof => f.write_str("end of samples"),
+            IQError::NotFound => f.write_str("no HackRF found"),
+            IQError::Disconnected => f.write_str("device disconnected"),
+            IQError::Overflow => f.write_str("samples dropped"),
+            IQError::Timeout => f.write_str("timed out waiting for samples"),
+            IQError::Device(e) => write!(f, "HackRF error: {e}"),
+            IQError::Unsupported => f.write_str("not supported by this source"),
+            IQError::AntennaPowerNotAllowed => f.write_str("antenna power is not enabled in the configuration"),
+            IQError::SampleRate(rate) => write!(f, "unsupported HackRF sample rate {rate} Hz, use 8, 10, 12.5, 16 or 20 MS/s"),
+            IQError::PlaybackSpeed(speed) => write!(f, "playback speed {speed} is not positive"),
+            IQError::NoChannels(Some(name)) => write!(f, "receiver {name} requires at least one frequency"),
+            IQError::NoChannels(None) => f.write_str("requires at least one frequency"),
+            IQError::ChannelOutOfRange(frequency) => write!(f, "channel {frequency} Hz is outside the band the receiver can decode"),
+            IQError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for IQError {}
+
+impl From<std::io::Error> for IQError {
+    fn from(e: std::io::Error) -> IQError {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => IQError::Eof,
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => IQError::Timeout,
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => IQError::Disconnected,
+            _ => IQError::Io(e),
+        }
+    }
+}
+
+impl From<async_libhackrf::Error> for IQError {
+    fn from(e: async_libhackrf::Error) -> IQError {
+        match e {
+            async_libhackrf::Error::Transfer(TransferError::Disconnected) => IQError::Disconnected,
+            async_libhackrf::Error::NotFound => IQError::NotFound,
+            e => IQError::Device(e),
+        }
+    }
+}
+
 pub trait IQSource {
-    fn read(&mut self) -> Vec<Complex<f32>>;
+    /// Wait for the next buffer of samples, which is never empty.
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError>;
+
+    /// Rate in samples per second of the buffers returned by `read`.
+    fn sample_rate(&self) -> f64;
+
+    /// Frequency the samples are centered on, if the source knows it.
+    fn center_frequency(&self) -> Option<f64> {
+        None
+    }
+
+    /// Gain the samples are currently being received with, for sources with adjustable gain.
+    fn gain(&self) -> Option<GainSettings> {
+        None
+    }
+
+    /// Take the next thing that happened to the source since it was last asked, such as the radio
+    /// dropping out.
+    fn poll_event(&mut self) -> Option<SourceEvent> {
+        None
+    }
+
+    /// Change a setting while the source is running.
+    fn command(&mut self, _command: SourceCommand) -> Result<(), IQError> {
+        Err(IQError::Unsupported)
+    }
+
+    /// What the radio is and how it is set up, for sources that are a radio.
+    fn diagnostics(&self) -> Option<Diagnostics> {
+        None
+    }
+
+    /// The kind of radio the samples come from, as recorded in a SigMF recording's `core:hw`.
+    fn hardware(&self) -> Option<String> {
+        None
+    }
+
+    /// Release the underlying device once no more samples are wanted.
+    fn stop(self: Box<Self>) {}
 }
 
+impl<S: IQSource + ?Sized> IQSource for Box<S> {
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        (**self).read()
+    }
+
+    fn sample_rate(&self) -> f64 {
+        (**self).sample_rate()
+    }
 
-const SAMPLE_RATE: f64 = 20_000_000.0;
-const MAX_GAIN_SETTING: i16 = 36;
-const MIN_GAIN_SETTING: i16 = 0;
+    fn center_frequency(&self) -> Option<f64> {
+        (**self).center_frequency()
+    }
+
+    fn gain(&self) -> Option<GainSettings> {
+        (**self).gain()
+    }
+
+    fn poll_event(&mut self) -> Option<SourceEvent> {
+        (**self).poll_event()
+    }
+
+    fn command(&mut self, command: SourceCommand) -> Result<(), IQError> {
+        (**self).command(command)
+    }
+
+    fn diagnostics(&self) -> Option<Diagnostics> {
+        (**self).diagnostics()
+    }
+
+    fn hardware(&self) -> Option<String> {
+        (**self).hardware()
+    }
+
+    fn stop(self: Box<Self>) {
+        (*self).stop()
+    }
+}
+
+/// A change to make to a running source, see [`DecoderHandle::command`](crate::DecoderHandle::command).
+#[derive(Clone, Debug)]
+pub enum SourceCommand {
+    /// Switch to a different way of setting the gain.
+    SetGain(GainControl),
+    /// Switch power to the antenna port on or off. It can only be switched on for a source that
+    /// was configured with antenna power.
+    SetAntennaPower(bool),
+}
+
+/// Something that happened to a source that the operator should know about.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum SourceEvent {
+    /// The radio went away, e.g. a bumped USB cable. Times are milliseconds since the Unix epoch.
+    Disconnected { at: u64 },
+    /// The radio is back and streaming with its previous settings.
+    Reconnected {
+        at: u64,
+        /// How long no samples were received for.
+        dropout_ms: u64,
+        /// Roughly how many samples the radio would have produced in that time.
+        samples_lost: u64,
+    },
+    /// The gain was changed, by the AGC or by hand.
+    GainChanged {
+        at: u64,
+        gain: GainSettings,
+    },
+    /// Power to the antenna port was switched on or off.
+    AntennaPower {
+        at: u64,
+        on: bool,
+    },
+    /// Samples were lost while streaming, because the decoder fell behind or a transfer failed.
+    Overrun {
+        at: u64,
+        /// How many samples were lost this time.
+        samples_lost: u64,
+        /// How many samples have been lost since the source was opened.
+        total_lost: u64,
+    },
+}
+
+impl SourceEvent {
+    /// Whether samples are missing before the next ones read, so that they do not follow on from
+    /// the last ones.
+    pub fn is_discontinuity(&self) -> bool {
+        matches!(self, SourceEvent::Reconnected { .. } | SourceEvent::Overrun { .. })
+    }
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
 
 
 pub struct FileIQSource {
-    file: BufReader<File>
+    file: BufReader<File>,
+    format: SampleFormat,
+    sample_rate: f64,
+    center: Option<f64>,
+    // offset of the first sample, past any header
+    data_start: u64,
+    // offset past the last sample, where a WAV file's data chunk ends
+    data_end: Option<u64>,
+    // bytes of a sample split across two reads
+    partial: Vec<u8>,
 }
 
 
 impl FileIQSource {
-    pub fn new(file_name: impl AsRef<Path>) -> FileIQSource {
-        FileIQSource {
-            file: BufReader::new(File::open(file_name.as_ref()).unwrap())
+    pub fn new(file_name: impl AsRef<Path>, format: SampleFormat, sample_rate: f64) -> Result<FileIQSource, std::io::Error> {
+        Ok(FileIQSource {
+            file: BufReader::new(File::open(file_name.as_ref())?),
+            format,
+            sample_rate,
+            center: None,
+            data_start: 0,
+            data_end: None,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Open a recording, working out its format and sample rate from SigMF metadata, a WAV header
+    /// or the file extension where they are not given.
+    ///
+    /// Raw recordings without a recognised extension are assumed to be `hackrf_transfer` captures,
+    /// and raw recordings without a given rate are assumed to be at [`DEFAULT_SAMPLE_RATE`].
+    pub fn open(file_name: impl AsRef<Path>, format: Option<SampleFormat>, sample_rate: Option<f64>) -> Result<FileIQSource, std::io::Error> {
+        let file_name = file_name.as_ref();
+        if sigmf::is_sigmf(file_name) {
+            let meta = sigmf::read_meta(file_name)?;
+            return Ok(FileIQSource {
+                file: BufReader::new(File::open(meta.data_path)?),
+                format: format.unwrap_or(meta.format),
+                sample_rate: sample_rate.unwrap_or(meta.sample_rate),
+                center: meta.center,
+                data_start: 0,
+                data_end: None,
+                partial: Vec::new(),
+            });
         }
+
+        let mut file = BufReader::new(File::open(file_name)?);
+        let is_wav = file_name.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+        let (detected_format, detected_rate, data_len) = if is_wav {
+            let header = wav::read_header(&mut file)?;
+            (header.format, header.sample_rate, header.data_len)
+        } else {
+            (SampleFormat::from_extension(file_name).unwrap_or(SampleFormat::Cs8), DEFAULT_SAMPLE_RATE, None)
+        };
+
+        let data_start = file.stream_position()?;
+        Ok(FileIQSource {
+            data_start,
+            data_end: data_len.map(|len| data_start + len),
+            file,
+            format: format.unwrap_or(detected_format),
+            sample_rate: sample_rate.unwrap_or(detected_rate),
+            center: None,
+            partial: Vec::new(),
+        })
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Continue reading from the `sample`th sample of the recording.
+    pub fn seek(&mut self, sample: u64) -> Result<(), std::io::Error> {
+        let offset = self.data_start + sample * self.format.sample_size() as u64;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.partial.clear();
+        Ok(())
     }
 }
 
 const BUF_SIZE: usize = 1024 * 1024;
 
-impl IQSource for FileIQSource {
-    fn read(&mut self) -> Vec<Complex<f32>> {
-        let mut buf = [0; BUF_SIZE];
-        match self.file.read(&mut buf) {
+/// Read and convert the next buffer of samples, carrying the bytes of a sample split across two
+/// reads over in `partial`.
+pub(crate) fn read_samples(reader: &mut impl Read, format: SampleFormat, partial: &mut Vec<u8>) -> Result<Vec<Complex<f32>>, IQError> {
+    let mut buf = vec![0; BUF_SIZE];
+    let mut out = Vec::new();
+    // a short read may not complete a single sample, so keep going until one does
+    while out.is_empty() {
+        let start = partial.len();
+        buf[..start].copy_from_slice(partial);
+        match reader.read(&mut buf[start..]) {
+            Ok(0) => return Err(IQError::Eof),
             Ok(ct) => {
-                let mut out = Vec::with_capacity(ct/2);
-                for i in (0..ct).step_by(2) {
-                    out.push(Complex::new(buf[i] as i8 as f32, buf[i+1] as i8 as f32))
-                }
-                out
+                let used = format.convert(&buf[..start + ct], &mut out);
+                partial.clear();
+                partial.extend_from_slice(&buf[used..start + ct]);
             }
-            Err(_) => vec![]
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
         }
     }
+    Ok(out)
+}
+
+impl IQSource for FileIQSource {
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        match self.data_end {
+            // stop before any chunks after the samples
+            Some(end) => {
+                let left = end.saturating_sub(self.file.stream_position()?);
+                read_samples(&mut self.file.by_ref().take(left), self.format, &mut self.partial)
+            }
+            None => read_samples(&mut self.file, self.format, &mut self.partial),
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn center_frequency(&self) -> Option<f64> {
+        self.center
+    }
+}
+
+/// Samples a [`HackRFIQSource`] has lost since it was opened, not counting while it was unplugged.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Overruns {
+    /// Transfers thrown away because they were not read quickly enough.
+    pub dropped_transfers: u64,
+    /// Transfers that failed.
+    pub failed_transfers: u64,
+    /// Samples in those transfers.
+    pub samples_lost: u64,
+    /// Longest time in milliseconds between two transfers arriving.
+    pub longest_gap_ms: u64,
+}
+
+/// What a HackRF is, read when it is opened.
+#[derive(Clone, Debug, Serialize)]
+pub struct BoardInfo {
+    /// Board type, e.g. "HackRF One".
+    pub board: String,
+    pub board_id: Option<u8>,
+    pub firmware: Option<String>,
+    pub serial: Option<String>,
+    /// USB API version, which decides what the firmware can do.
+    pub api_version: String,
+}
+
+impl BoardInfo {
+    /// Read everything there is to know about the board, before it starts streaming.
+    fn read(hack_rf: &HackRfOne<UnknownMode>) -> BoardInfo {
+        let board_id = hack_rf.board_id().ok();
+        BoardInfo {
+            board: board_id.map_or("unknown", async_libhackrf::board_name).to_string(),
+            board_id,
+            firmware: hack_rf.version().ok(),
+            serial: hack_rf.serial_number().ok(),
+            api_version: hack_rf.device_version().to_string(),
+        }
+    }
+}
+
+impl Display for BoardInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} serial {} firmware {} API {}",
+            self.board,
+            self.serial.as_deref().unwrap_or("unknown"),
+            self.firmware.as_deref().unwrap_or("unknown"),
+            self.api_version)
+    }
+}
+
+/// The state of a radio, see [`IQSource::diagnostics`].
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostics {
+    pub board: BoardInfo,
+    /// Whether the radio is plugged in.
+    pub connected: bool,
+    /// Samples per second, made by dividing a clock of `sample_clock_hz` by `sample_divider`.
+    pub sample_rate: f64,
+    pub sample_clock_hz: u32,
+    pub sample_divider: u32,
+    /// Frequency the radio is tuned to in Hz.
+    pub center: f64,
+    pub gain: GainSettings,
+    /// Whether the antenna port is powering something, such as a mast mounted LNA.
+    pub antenna_power: bool,
+    /// Whether the antenna port may be powered.
+    pub antenna_power_allowed: bool,
+    pub overruns: Overruns,
 }
 
 pub struct HackRFIQSource {
-    current_gain: i16,
-    max_iq_reading: i8,
-    amp_adjust_time: i64,
-    hack_rf: HackRfOne<RxMode>
+    gain: GainSettings,
+    controller: Box<dyn GainController + Send>,
+    sample_rate: f64,
+    center: f64,
+    // opens the radio again after it is unplugged
+    connect: Box<dyn FnMut() -> Option<HackRfOne<UnknownMode>> + Send>,
+    // None while the radio is unplugged
+    hack_rf: Option<HackRfOne<RxMode>>,
+    // the transfers the radio streams in, which is what a failed one loses
+    rx_config: RxConfig,
+    disconnected_at: Option<Instant>,
+    events: VecDeque<SourceEvent>,
+    overruns: Overruns,
+    board: BoardInfo,
+    antenna_power: bool,
+    antenna_power_allowed: bool,
 }
 
+/// How often to look for an unplugged HackRF coming back.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
 extern "C" { fn hackrf_get_sample_rate(freq: f64, freq_hz: *mut u32, divider: *mut u32); }
 
-fn gain_index(idx: i16) -> (i16, i16) {
-    if idx < 0 {
-        (0, 0)
-    } else if idx <= 5 {
-        (idx * 8, 0)
-    } else if idx <= 5 + 31 {
-        (40, (idx - 5) * 2)
-    } else {
-        (40, 62)
+/// The HackRF's sample clock frequency and divider for `sample_rate`.
+pub(crate) fn sample_rate_params(sample_rate: f64) -> (u32, u32) {
+    let mut freq_hz = 0;
+    let mut divider = 0;
+    unsafe {
+        hackrf_get_sample_rate(sample_rate, &mut freq_hz, &mut divider);
     }
+    (freq_hz, divider)
+}
+
+fn set_gain<MODE>(hack_rf: &mut HackRfOne<MODE>, gain: GainSettings) -> Result<(), async_libhackrf::Error> {
+    hack_rf.set_amp_enable(gain.amp)?;
+    hack_rf.set_vga_gain(gain.vga)?;
+    hack_rf.set_lna_gain(gain.lna)
+}
+
+/// Open the HackRF with the given serial number, or the first one not in use without one.
+pub(crate) fn open_radio(serial: Option<&str>) -> Result<HackRfOne<UnknownMode>, IQError> {
+    match serial {
+        Some(serial) => Ok(HackRfOne::open_by_serial(serial)?),
+        None => HackRfOne::new().ok_or(IQError::NotFound),
+    }
+}
+
+fn open_hack_rf(
+    hack_rf: Option<HackRfOne<UnknownMode>>,
+    center: f64,
+    sample_rate: f64,
+    gain: GainSettings,
+    antenna_power: bool,
+    rx_config: RxConfig,
+) -> Result<(HackRfOne<RxMode>, BoardInfo), IQError> {
+    let mut hack_rf = hack_rf.ok_or(IQError::NotFound)?;
+    let board = BoardInfo::read(&hack_rf);
+
+    let (freq_hz, divider) = sample_rate_params(sample_rate);
+    hack_rf.set_sample_rate(freq_hz, divider)?;
+    hack_rf.set_freq(center as u64)?;
+    set_gain(&mut hack_rf, gain)?;
+    // set either way, as the antenna port could have been left powered by another program
+    hack_rf.set_antenna_enable(antenna_power.into())?;
+    Ok((hack_rf.into_rx_mode_with(rx_config)?, board))
 }
 
 impl HackRFIQSource {
-    pub fn new(center: f64) -> Result<HackRFIQSource, async_libhackrf::Error> {
-        let mut hack_rf = HackRfOne::new().unwrap();
-
-        let mut freq_hz = 0;
-        let mut divider = 0;
-        unsafe {
-            hackrf_get_sample_rate(SAMPLE_RATE, &mut freq_hz, &mut divider);
-        }
-        hack_rf.set_sample_rate(freq_hz, divider)?;
-        hack_rf.set_freq(center as u64)?;
-        hack_rf.set_amp_enable(true)?;
-        let (lna, vga) = gain_index(6);
-        hack_rf.set_vga_gain(vga as u16)?;
-        hack_rf.set_lna_gain(lna as u16)?;
-        let hack_rf = hack_rf.into_rx_mode().unwrap();
+    pub fn new(center: f64, sample_rate: f64) -> Result<HackRFIQSource, IQError> {
+        HackRFIQSource::open(None, center, sample_rate, GainControl::default())
+    }
+
+    /// Open the HackRF with the given serial number, or the first one not in use without one.
+    /// The same radio is waited for if it is unplugged.
+    pub fn open(serial: Option<&str>, center: f64, sample_rate: f64, gain: GainControl) -> Result<HackRFIQSource, IQError> {
+        let hack_rf = open_radio(serial)?;
+        // after a dropout look for this radio by its full serial number, not whichever is free,
+        // which could be someone else's
+        let serial = hack_rf.serial_number().ok().or_else(|| serial.map(str::to_string));
+        let mut first = Some(hack_rf);
+        let connect = move || match (first.take(), &serial) {
+            (Some(hack_rf), _) => Some(hack_rf),
+            (None, Some(serial)) => HackRfOne::open_by_serial(serial).ok(),
+            (None, None) => HackRfOne::new(),
+        };
+        HackRFIQSource::with_radio(connect, center, sample_rate, gain)
+    }
+
+    /// Read the checksum of the CPLD's configuration from the HackRF with the given serial number,
+    /// or the first one not in use without one.
+    ///
+    /// This is a diagnostic to run by hand on an idle radio, never while streaming: `hackrf_info`
+    /// leaves it out until HackRF issue #609 is fixed.
+    pub fn cpld_checksum(serial: Option<&str>) -> Result<u32, IQError> {
+        Ok(open_radio(serial)?.cpld_checksum()?)
+    }
+
+    /// Receive from whatever radio `connect` opens, which is called again to get it back after it
+    /// is unplugged. With a [`SimulatedTransport`](async_libhackrf::SimulatedTransport) this
+    /// exercises the gain control and reconnecting without a HackRF.
+    pub fn with_radio(
+        mut connect: impl FnMut() -> Option<HackRfOne<UnknownMode>> + Send + 'static,
+        center: f64,
+        sample_rate: f64,
+        gain: GainControl,
+    ) -> Result<HackRFIQSource, IQError> {
+        let initial = gain.initial();
+        let rx_config = RxConfig::default();
+        let (hack_rf, board) = open_hack_rf(connect(), center, sample_rate, initial, false, rx_config)?;
+        println!("Opened {board}");
 
         Ok(HackRFIQSource {
-            current_gain: 6,
-            max_iq_reading: 0,
-            amp_adjust_time: SAMPLE_RATE as i64,
-            hack_rf
+            gain: initial,
+            controller: gain.controller(sample_rate, initial),
+            sample_rate,
+            center,
+            connect: Box::new(connect),
+            hack_rf: Some(hack_rf),
+            rx_config,
+            disconnected_at: None,
+            events: VecDeque::new(),
+            overruns: Overruns::default(),
+            board,
+            antenna_power: false,
+            antenna_power_allowed: false,
         })
     }
+
+    /// Allow power on the antenna port, for a mast mounted LNA, and switch it on.
+    ///
+    /// Until this is called the port is kept unpowered and [`SourceCommand::SetAntennaPower`]
+    /// cannot switch it on, as the 3.3 V it puts on the antenna cable can damage equipment that
+    /// does not expect it.
+    pub fn enable_antenna_power(&mut self) -> Result<(), IQError> {
+        self.antenna_power_allowed = true;
+        self.set_antenna_power(true)
+    }
+
+    fn set_antenna_power(&mut self, on: bool) -> Result<(), IQError> {
+        if on && !self.antenna_power_allowed {
+            return Err(IQError::AntennaPowerNotAllowed);
+        }
+        if let Some(hack_rf) = &mut self.hack_rf {
+            hack_rf.set_antenna_enable(on.into())?;
+        }
+        // applied when the radio comes back if it is unplugged
+        self.antenna_power = on;
+        println!("Antenna power {}", if on { "on" } else { "off" });
+        self.events.push_back(SourceEvent::AntennaPower { at: unix_millis(SystemTime::now()), on });
+        Ok(())
+    }
+
+    /// Samples lost so far.
+    pub fn overruns(&self) -> Overruns {
+        self.overruns
+    }
+
+    /// Replace the gain control with a custom one.
+    pub fn set_gain_controller(&mut self, controller: Box<dyn GainController + Send>) {
+        self.controller = controller;
+    }
+
+    fn disconnected(&mut self) {
+        eprintln!("HackRF disconnected, waiting for it to come back");
+        self.hack_rf = None;
+        self.disconnected_at = Some(Instant::now());
+        self.events.push_back(SourceEvent::Disconnected { at: unix_millis(SystemTime::now()) });
+    }
+
+    /// Try to open the radio again with the settings it had before it was unplugged.
+    fn reconnect(&mut self) -> Result<(), IQError> {
+        thread::sleep(RECONNECT_INTERVAL);
+        let (hack_rf, board) = open_hack_rf(
+            (self.connect)(),
+            self.center,
+            self.sample_rate,
+            self.gain,
+            self.antenna_power,
+            self.rx_config,
+        )?;
+        self.hack_rf = Some(hack_rf);
+        // it may be a different radio with the same serial number, or have been reflashed
+        self.board = board;
+
+        if let Some(disconnected_at) = self.disconnected_at.take() {
+            let dropout = disconnected_at.elapsed();
+            self.events.push_back(SourceEvent::Reconnected {
+                at: unix_millis(SystemTime::now()),
+                dropout_ms: dropout.as_millis() as u64,
+                samples_lost: (dropout.as_secs_f64() * self.sample_rate) as u64,
+            });
+        }
+        Ok(())
+    }
+
+    fn lost(&mut self, samples: u64) {
+        self.overruns.samples_lost += samples;
+        self.events.push_back(SourceEvent::Overrun {
+            at: unix_millis(SystemTime::now()),
+            samples_lost: samples,
+            total_lost: self.overruns.samples_lost,
+        });
+    }
+
+    fn change_gain(&mut self, gain: GainSettings) {
+        let Some(hack_rf) = &mut self.hack_rf else {
+            // applied when the radio comes back
+            self.gain = gain;
+            return;
+        };
+        // the radio takes gain changes without interrupting the stream
+        match set_gain(hack_rf, gain) {
+            Ok(()) => {
+                println!("gain = {},{} amp {}", gain.vga, gain.lna, gain.amp);
+                self.gain = gain;
+                self.events.push_back(SourceEvent::GainChanged { at: unix_millis(SystemTime::now()), gain });
+            }
+            // a disconnect shows up on the next read
+            Err(e) => eprintln!("Failed to change gain: {e}"),
+        }
+    }
 }
 
 impl IQSource for HackRFIQSource {
-    fn read(&mut self) -> Vec<Complex<f32>> {
-        let buffer = self.hack_rf.rx().unwrap();
-        let mut out = Vec::with_capacity(buffer.len() / 2);
-        for (i, q) in buffer.into_iter().tuples() {
-            self.max_iq_reading = i8::max(self.max_iq_reading, i as i8);
-            self.max_iq_reading = i8::max(self.max_iq_reading, q as i8);
-            out.push(Complex::new((i as i8) as f32, (q as i8) as f32));
-        }
-
-        self.amp_adjust_time -= out.len() as i64;
-        if self.amp_adjust_time <= 0 {
-            let changed;
-            let new_gain;
-            if self.max_iq_reading < 40 {
-                new_gain = i16::min(self.current_gain + 1, MAX_GAIN_SETTING);
-                changed = true;
-            } else if self.max_iq_reading > 90 {
-                new_gain = i16::max(self.current_gain - 1, MIN_GAIN_SETTING);
-                changed = true;
-            } else {
-                new_gain = 0;
-                changed = false;
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        // only make one attempt per read so that the reader can still be stopped while the radio is away
+        if self.hack_rf.is_none() && self.reconnect().is_err() {
+            return Err(IQError::Timeout);
+        }
+        let Some(hack_rf) = &mut self.hack_rf else {
+            return Err(IQError::Timeout);
+        };
+        let read = hack_rf.rx_transfer().map_err(IQError::from);
+        let gap = hack_rf.stats().longest_gap.as_millis() as u64;
+        self.overruns.longest_gap_ms = self.overruns.longest_gap_ms.max(gap);
+        let transfer = match read {
+            Ok(transfer) => transfer,
+            Err(IQError::Disconnected) => {
+                self.disconnected();
+                return Err(IQError::Timeout);
+            }
+            // the radio carries on with the next transfer
+            Err(IQError::Device(async_libhackrf::Error::Transfer(e))) => {
+                eprintln!("HackRF transfer failed: {e}");
+                self.overruns.failed_transfers += 1;
+                self.lost(self.rx_config.transfer_size as u64 / 2);
+                return Err(IQError::Overflow);
+            }
+            Err(e) => return Err(e),
+        };
+        let buffer = transfer.data;
+        if transfer.skipped > 0 {
+            self.overruns.dropped_transfers += transfer.skipped;
+            self.lost(transfer.skipped * buffer.len() as u64 / 2);
+        }
+        let out: Vec<Complex<f32>> = buffer.into_iter()
+            .tuples()
+            .map(|(i, q)| Complex::new((i as i8) as f32, (q as i8) as f32))
+            .collect();
+
+        if let Some(gain) = self.controller.update(&out, self.gain) {
+            self.change_gain(gain);
+        }
+
+        Ok(out)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn center_frequency(&self) -> Option<f64> {
+        Some(self.center)
+    }
+
+    fn gain(&self) -> Option<GainSettings> {
+        Some(self.gain)
+    }
+
+    fn poll_event(&mut self) -> Option<SourceEvent> {
+        self.events.pop_front()
+    }
+
+    fn command(&mut self, command: SourceCommand) -> Result<(), IQError> {
+        match command {
+            SourceCommand::SetGain(control) => {
+                println!("Gain control set to {control}");
+                self.controller = control.controller(self.sample_rate, self.gain);
             }
+            SourceCommand::SetAntennaPower(on) => self.set_antenna_power(on)?,
+        }
+        Ok(())
+    }
 
-            if changed {
-                // eprintln!("Oh no we couldn't change stuff");
-                let (lna, vga) = gain_index(new_gain);
-                unsafe {
-                    // the unsafe is a workaround so that we can move out to the hack_rf
-                    // we need to move out to call stop_rx
-                    let mut hack_rf = std::ptr::read(&self.hack_rf).stop_rx().unwrap();
-                    hack_rf.set_vga_gain(vga as u16).unwrap();
-                    hack_rf.set_lna_gain(lna as u16).unwrap();
-                    println!("gain = {vga},{lna} max = {}", self.max_iq_reading);
-                    std::ptr::write(&mut self.hack_rf, hack_rf.into_rx_mode().unwrap());
-                }
-                self.current_gain = new_gain;
+    fn diagnostics(&self) -> Option<Diagnostics> {
+        let (sample_clock_hz, sample_divider) = sample_rate_params(self.sample_rate);
+        Some(Diagnostics {
+            board: self.board.clone(),
+            connected: self.hack_rf.is_some(),
+            sample_rate: self.sample_rate,
+            sample_clock_hz,
+            sample_divider,
+            center: self.center,
+            gain: self.gain,
+            antenna_power: self.antenna_power,
+            antenna_power_allowed: self.antenna_power_allowed,
+            overruns: self.overruns,
+        })
+    }
+
+    fn hardware(&self) -> Option<String> {
+        Some(self.board.board.clone())
+    }
+
+    fn stop(self: Box<Self>) {
+        let Some(mut hack_rf) = self.hack_rf else {
+            return;
+        };
+        // nothing should be powered while nobody is listening
+        if self.antenna_power {
+            if let Err(e) = hack_rf.set_antenna_enable(0) {
+                eprintln!("Failed to switch off antenna power: {e}");
             }
-            self.max_iq_reading = 0;
-            self.amp_adjust_time = SAMPLE_RATE as i64;
         }
+        if let Err(e) = hack_rf.stop_rx() {
+            eprintln!("Failed to stop HackRF: {e}");
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use async_libhackrf::{HackRfOne, Request, SimulatedTransport};
+    use super::*;
+
+    const CENTER: f64 = 436_850_000.0;
+    const SAMPLE_RATE: f64 = 2_000_000.0;
+
+    /// A radio receiving a constant `level` on the ±128 scale.
+    fn simulated(level: i8) -> SimulatedTransport {
+        SimulatedTransport::new()
+            .samples([level as u8, level as u8].repeat(1024))
+            .speed(None)
+    }
+
+    fn open(radio: &SimulatedTransport, gain: GainControl) -> HackRFIQSource {
+        let radio = radio.clone();
+        let connect = move || Some(HackRfOne::with_transport(radio.clone()));
+        HackRFIQSource::with_radio(connect, CENTER, SAMPLE_RATE, gain).unwrap()
+    }
 
-        out
+    /// Peak AGC deciding after every transfer.
+    fn fast_agc() -> GainControl {
+        GainControl::Peak { low: 40.0, high: 90.0, window_ms: 1, amp: false }
     }
-}
\ No newline at end of file
+
+    fn events(source: &mut HackRFIQSource) -> Vec<SourceEvent> {
+        std::iter::from_fn(|| source.poll_event()).collect()
+    }
+
+    #[test]
+    fn opens_with_settings() {
+        let radio = simulated(0);
+        let source = open(&radio, GainControl::Manual { lna: 16, vga: 20, amp: true });
+        let settings = radio.settings();
+        assert_eq!(settings.frequency, Some(CENTER as u64));
+        assert_eq!(settings.sample_rate, Some(sample_rate_params(SAMPLE_RATE)));
+        assert_eq!((settings.lna_gain, settings.vga_gain, settings.amp_enable), (Some(16), Some(20), Some(true)));
+        assert_eq!(settings.antenna_enable, Some(false));
+        assert_eq!(source.diagnostics().unwrap().board.serial.as_deref(), Some("00000000000000005a6f7e8d0123abcd"));
+    }
+
+    #[test]
+    fn agc_turns_strong_signal_down() {
+        let radio = simulated(120);
+        let mut source = open(&radio, fast_agc());
+        assert_eq!((radio.settings().lna_gain, radio.settings().vga_gain), (Some(40), Some(2)));
+
+        // the VGA goes first, then the LNA in 8 dB steps
+        for expected in [(40, 0), (32, 0), (24, 0)] {
+            source.read().unwrap();
+            assert_eq!((radio.settings().lna_gain, radio.settings().vga_gain), (Some(expected.0), Some(expected.1)));
+        }
+        assert_eq!(source.gain().map(|gain| (gain.lna, gain.vga)), Some((24, 0)));
+        let changes = events(&mut source).into_iter()
+            .filter(|event| matches!(event, SourceEvent::GainChanged { .. }))
+            .count();
+        assert_eq!(changes, 3);
+    }
+
+    #[test]
+    fn agc_turns_weak_signal_up() {
+        let radio = simulated(1);
+        let mut source = open(&radio, fast_agc());
+        for vga in [4, 6, 8] {
+            source.read().unwrap();
+            assert_eq!((radio.settings().lna_gain, radio.settings().vga_gain), (Some(40), Some(vga)));
+        }
+    }
+
+    #[test]
+    fn agc_leaves_good_signal_alone() {
+        let radio = simulated(60);
+        let mut source = open(&radio, fast_agc());
+        for _ in 0..3 {
+            source.read().unwrap();
+        }
+        assert_eq!((radio.settings().lna_gain, radio.settings().vga_gain), (Some(40), Some(2)));
+        assert!(events(&mut source).is_empty());
+    }
+
+    #[test]
+    fn failed_gain_change_keeps_streaming() {
+        let radio = simulated(120);
+        let mut source = open(&radio, fast_agc());
+        radio.stall(Request::SetLnaGain);
+
+        assert!(source.read().is_ok());
+        assert_eq!(radio.settings().lna_gain, Some(40));
+        // the gain reported is still what the radio was last set to
+        assert_eq!(source.gain().map(|gain| (gain.lna, gain.vga)), Some((40, 2)));
+        assert!(events(&mut source).is_empty());
+        assert!(source.read().is_ok());
+    }
+
+    #[test]
+    fn manual_gain_from_command() {
+        let radio = simulated(120);
+        let mut source = open(&radio, fast_agc());
+        source.command(SourceCommand::SetGain(GainControl::Manual { lna: 8, vga: 30, amp: false })).unwrap();
+        source.read().unwrap();
+        assert_eq!((radio.settings().lna_gain, radio.settings().vga_gain), (Some(8), Some(30)));
+        // and it stays there whatever the signal
+        source.read().unwrap();
+        assert_eq!((radio.settings().lna_gain, radio.settings().vga_gain), (Some(8), Some(30)));
+    }
+
+    #[test]
+    fn antenna_power_needs_allowing() {
+        let radio = simulated(0);
+        let mut source = open(&radio, fast_agc());
+        assert!(matches!(source.command(SourceCommand::SetAntennaPower(true)), Err(IQError::AntennaPowerNotAllowed)));
+        assert_eq!(radio.settings().antenna_enable, Some(false));
+
+        source.enable_antenna_power().unwrap();
+        assert_eq!(radio.settings().antenna_enable, Some(true));
+        Box::new(source).stop();
+        assert_eq!(radio.settings().antenna_enable, Some(false));
+    }
+
+    #[test]
+    fn reconnects_after_unplug() {
+        let radio = simulated(60);
+        let mut source = open(&radio, GainControl::Manual { lna: 16, vga: 20, amp: false });
+        source.read().unwrap();
+        radio.unplug();
+
+        // transfers that arrived before the cable was pulled are read first
+        let mut reads = 0;
+        while source.read().is_ok() {
+            reads += 1;
+            assert!(reads <= RxConfig::default().backlog, "still reading after unplugging");
+        }
+        assert!(!source.diagnostics().unwrap().connected);
+        assert!(matches!(events(&mut source)[..], [SourceEvent::Disconnected { .. }]));
+
+        // still gone
+        assert!(matches!(source.read(), Err(IQError::Timeout)));
+        assert!(events(&mut source).is_empty());
+
+        radio.plug_in();
+        // the first read after coming back opens it again
+        let _ = source.read();
+        assert!(source.read().is_ok());
+        assert!(source.diagnostics().unwrap().connected);
+        assert!(matches!(events(&mut source)[..], [SourceEvent::Reconnected { .. }]));
+        // with the same settings as before
+        let settings = radio.settings();
+        assert_eq!(settings.frequency, Some(CENTER as u64));
+        assert_eq!((settings.lna_gain, settings.vga_gain), (Some(16), Some(20)));
+        assert_eq!(settings.transceiver_mode, Some(1));
+    }
+
+    /// A cu8 WAV file of `samples` followed by a LIST chunk, as written by SDR# and SDR++.
+    fn wav_with_tags(samples: &[u8]) -> Vec<u8> {
+        let tags = b"INFOISFT\x06\0\0\0SDR++\0";
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend(b"fmt \x10\0\0\0\x01\0\x02\0");
+        wav.extend(2_000_000u32.to_le_bytes());
+        wav.extend(4_000_000u32.to_le_bytes());
+        wav.extend(b"\x02\0\x08\0");
+        wav.extend(b"data");
+        wav.extend((samples.len() as u32).to_le_bytes());
+        wav.extend(samples);
+        wav.extend(b"LIST");
+        wav.extend((tags.len() as u32).to_le_bytes());
+        wav.extend(tags);
+        wav
+    }
+
+    #[test]
+    fn wav_stops_at_end_of_data() {
+        let path = std::env::temp_dir().join(format!("telemega-wav-{}.wav", std::process::id()));
+        std::fs::write(&path, wav_with_tags(&[128, 128, 255, 128, 128, 0])).unwrap();
+        let mut source = FileIQSource::open(&path, None, None).unwrap();
+        assert_eq!(source.format(), SampleFormat::Cu8);
+        assert_eq!(source.sample_rate(), 2_000_000.0);
+
+        let mut samples = Vec::new();
+        loop {
+            match source.read() {
+                Ok(read) => samples.extend(read),
+                Err(IQError::Eof) => break,
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(samples.len(), 3);
+        // seeking back still stops there
+        source.seek(2).unwrap();
+        assert_eq!(source.read().unwrap().len(), 1);
+        assert!(matches!(source.read(), Err(IQError::Eof)));
+        drop(source);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
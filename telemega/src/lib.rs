@@ -1,93 +1,526 @@
-mod shifter;
+mod nco;
+mod filter;
+mod channelizer;
 mod streaming_gfsk;
 mod ao;
 mod iq_source;
 mod packet;
 mod packet_types;
+mod config;
+mod received_packet;
+mod sample_format;
+mod wav;
+mod sigmf;
+mod paced_source;
+mod recording_source;
+mod rtl_tcp;
+mod stream_source;
+mod dedupe;
+mod gain;
+mod transmit;
+mod sweep;
 
 use std::collections::VecDeque;
 use std::io::Write;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Instant;
-use clap::Parser;
+use std::ops::RangeInclusive;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use num_complex::Complex;
 use bus::Bus;
-use crate::iq_source::{FileIQSource, HackRFIQSource, IQSource};
+use serde::Serialize;
+use crate::channelizer::{decimation_factor, max_offset, retune_range, Channelizer};
+use crate::dedupe::Deduplicator;
 use crate::packet::Packet;
 use crate::packet_types::decode;
-use crate::streaming_gfsk::StreamingGFSKDecoder;
+use crate::streaming_gfsk::{StreamingGFSKDecoder, TOTAL_PACKET_BITS};
 
 pub use crate::packet_types::*;
+pub use crate::config::*;
+pub use crate::received_packet::ReceivedPacket;
+pub use crate::sample_format::SampleFormat;
+pub use crate::iq_source::{BoardInfo, Diagnostics, FileIQSource, HackRFIQSource, IQError, IQSource, Overruns, SourceCommand, SourceEvent};
+pub use crate::gain::{GainControl, GainController, GainSettings};
+pub use crate::sigmf::{SigMfAnnotator, SigMfWriter};
+pub use crate::paced_source::PacedIQSource;
+pub use crate::recording_source::{RawRecording, RecordingIQSource};
+pub use crate::rtl_tcp::{RtlTcpIQSource, DEFAULT_RTL_SAMPLE_RATE};
+pub use crate::stream_source::StreamIQSource;
+pub use crate::sweep::{BandScan, Carrier, HackRFSweeper, Spectrum};
+pub use crate::transmit::{HackRFTransmitter, TelemetryModulator, TestFlight};
 
-const HZ: f64 = 20_000_000.0;
-const BAUD: f64 = 38400.0;
 
+/// Something that happened to one of the sources, tagged with the receiver it happened to.
+#[derive(Clone, Debug, Serialize)]
+pub struct DecoderEvent {
+    /// Name of the receiver, `None` for the main one.
+    pub source: Option<String>,
+    #[serde(flatten)]
+    pub event: SourceEvent,
+}
+
+/// The state of one receiver's radio, see [`DecoderHandle::diagnostics`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ReceiverDiagnostics {
+    /// Name of the receiver, `None` for the main one.
+    pub source: Option<String>,
+    #[serde(flatten)]
+    pub diagnostics: Diagnostics,
+}
 
-#[derive(Parser, Debug)]
-#[command(version, about)]
-struct Arguments {
-    frequencies: Vec<f64>,
-    #[arg(short, long)]
-    file: Option<PathBuf>
+/// Handle to a running decoder pipeline returned by [`start_decoders`].
+///
+/// Dropping the handle leaves the pipeline running in the background; call [`DecoderHandle::stop`]
+/// followed by [`DecoderHandle::join`] (or just [`DecoderHandle::shutdown`]) to stop it cleanly.
+pub struct DecoderHandle {
+    running: Arc<AtomicBool>,
+    readers: Vec<Reader>,
+    workers: Vec<JoinHandle<()>>,
+    events: mpsc::Receiver<DecoderEvent>,
+    commands: Vec<(Option<String>, mpsc::Sender<SourceCommand>)>,
+    retunes: Vec<(Option<String>, Vec<Tuning>)>,
+    diagnostics: Vec<(Option<String>, SharedDiagnostics)>,
+    start: Instant,
 }
 
-impl Arguments {
-    fn get_center(&self) -> f64 {
-        self.frequencies.iter()
-            .max_by(|a, b| a.total_cmp(b))
-            .map_or(0.0, |max| max + 100_000.0)
+impl DecoderHandle {
+    /// Ask the pipeline to stop without waiting for it.
+    ///
+    /// The readers stop pulling samples and release their sources (taking HackRFs out of RX mode),
+    /// then every channel worker decodes whatever is still queued on its bus and exits.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Take everything that has happened to the sources since the last call.
+    pub fn events(&self) -> Vec<DecoderEvent> {
+        self.events.try_iter().collect()
+    }
+
+    /// Pass `command` to the named receiver's source, or the main one for `None`. It is carried
+    /// out before the next read, and failures are logged.
+    ///
+    /// Returns false if there is no such receiver or it has stopped.
+    pub fn command(&self, receiver: Option<&str>, command: SourceCommand) -> bool {
+        self.commands.iter()
+            .find(|(name, _)| name.as_deref() == receiver)
+            .is_some_and(|(_, sender)| sender.send(command).is_ok())
+    }
+
+    /// Move channel `channel`, counting from 0 in the order they were configured, of the named
+    /// receiver or the main one for `None` to `frequency` Hz, e.g. to follow a drifting
+    /// transmitter. Its decoder makes the move before decoding any more samples.
+    ///
+    /// Only small moves can be made, as the channel stays within the band split out for it when
+    /// decoding started, and fails with [`IQError::ChannelOutOfRange`] for one too far. Returns
+    /// false if there is no such receiver or channel or it has stopped.
+    pub fn retune(&self, receiver: Option<&str>, channel: usize, frequency: f64) -> Result<bool, IQError> {
+        let Some(tuning) = self.retunes.iter()
+            .find(|(name, _)| name.as_deref() == receiver)
+            .and_then(|(_, tunings)| tunings.get(channel)) else {
+            return Ok(false);
+        };
+        if !tuning.range.contains(&frequency) {
+            return Err(IQError::ChannelOutOfRange(frequency));
+        }
+        Ok(tuning.sender.send(frequency).is_ok())
+    }
+
+    /// The state of every receiver that is a radio, as of its last change, apart from the longest
+    /// gap between transfers which may be up to a second old.
+    pub fn diagnostics(&self) -> Vec<ReceiverDiagnostics> {
+        self.diagnostics.iter()
+            .filter_map(|(name, diagnostics)| {
+                let diagnostics = diagnostics.lock().unwrap().clone()?;
+                Some(ReceiverDiagnostics { source: name.clone(), diagnostics })
+            })
+            .collect()
     }
+
+    /// Whether any source is still producing samples.
+    pub fn is_running(&self) -> bool {
+        self.readers.iter().any(|reader| !reader.is_finished())
+    }
+
+    /// Wait for the readers and all channel workers to exit, returning the first error that
+    /// stopped a source if one did not run out of samples or get stopped.
+    pub fn join(self) -> Result<(), IQError> {
+        let mut result = Ok(());
+        for reader in self.readers {
+            let stopped = reader.join().unwrap_or_else(|_| {
+                eprintln!("Sample reader panicked");
+                Ok(())
+            });
+            result = result.and(stopped);
+        }
+        for worker in self.workers {
+            if worker.join().is_err() {
+                eprintln!("Decoder worker panicked");
+            }
+        }
+        println!("Took {:?}", Instant::now() - self.start);
+        result
+    }
+
+    /// Stop the pipeline and wait for it to finish, returning the first error that stopped a
+    /// source, as [`DecoderHandle::stop`] followed by [`DecoderHandle::join`].
+    pub fn shutdown(self) -> Result<(), IQError> {
+        self.stop();
+        self.join()
+    }
+}
+
+/// Latest diagnostics of a receiver's source, kept up to date by its reader.
+type SharedDiagnostics = Arc<Mutex<Option<Diagnostics>>>;
+
+/// How often a reader refreshes its diagnostics when nothing has happened to its source.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A receiver's reader thread, which ends with the error that stopped its source, if any.
+type Reader = JoinHandle<Result<(), IQError>>;
+
+/// What the rest of the pipeline passes to and from a reader while it is running.
+struct ReaderControl {
+    commands: mpsc::Receiver<SourceCommand>,
+    diagnostics: SharedDiagnostics,
+}
+
+/// Where a channel's worker takes new frequencies from, see [`DecoderHandle::retune`].
+struct Tuning {
+    sender: mpsc::Sender<f64>,
+    /// Frequencies the channel can be moved to.
+    range: RangeInclusive<f64>,
+}
+
+/// Samples passed from a reader to its channelizer, and from there to each channel's worker.
+struct SampleBlock {
+    samples: Vec<Complex<f32>>,
+    /// Samples were lost between the previous block and this one.
+    discontinuity: bool,
+}
+
+/// One opened source and the channels to decode from it.
+struct Receiver {
+    name: Option<String>,
+    src: Box<dyn IQSource + Send>,
+    channels: Vec<Channel>,
+    center: f64,
+}
+
+fn open_source(config: &DecoderConfig, source: &SourceConfig, center: f64, sample_rate: Option<f64>) -> Result<Box<dyn IQSource + Send>, IQError> {
+    Ok(match source {
+        SourceConfig::File { path, format } if stream_source::is_stream(path) => {
+            let mut stream = if path.as_os_str() == "-" {
+                StreamIQSource::stdin(*format, sample_rate)?
+            } else {
+                StreamIQSource::open(path, *format, sample_rate)?
+            };
+            if !config.start_offset.is_zero() {
+                let offset = config.start_offset.as_secs_f64() * stream.sample_rate();
+                stream.skip(offset as u64)?;
+            }
+            match config.playback_speed {
+                Some(speed) => Box::new(PacedIQSource::new(stream, speed)?),
+                None => Box::new(stream),
+            }
+        }
+        SourceConfig::File { path, format } => {
+            let mut file = FileIQSource::open(path, *format, sample_rate)?;
+            if !config.start_offset.is_zero() {
+                let offset = config.start_offset.as_secs_f64() * file.sample_rate();
+                file.seek(offset as u64)?;
+            }
+            match config.playback_speed {
+                Some(speed) => Box::new(PacedIQSource::new(file, speed)?),
+                None => Box::new(file),
+            }
+        }
+        SourceConfig::HackRf { serial, gain, antenna_power } => {
+            let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+            if !HACKRF_SAMPLE_RATES.contains(&sample_rate) {
+                return Err(IQError::SampleRate(sample_rate));
+            }
+            let mut hack_rf = HackRFIQSource::open(serial.as_deref(), center, sample_rate, gain.clone())?;
+            if *antenna_power {
+                if let Err(e) = hack_rf.enable_antenna_power() {
+                    Box::new(hack_rf).stop();
+                    return Err(e);
+                }
+            }
+            Box::new(hack_rf)
+        }
+        SourceConfig::RtlTcp { address, gain } => {
+            let sample_rate = sample_rate.unwrap_or(DEFAULT_RTL_SAMPLE_RATE);
+            Box::new(RtlTcpIQSource::connect(address.as_str(), center, sample_rate, *gain)?)
+        }
+    })
 }
 
-pub fn start_decoders(new_packet: impl Fn(f64, DecodedPacket) + Sync) {
+fn open_receivers(config: &DecoderConfig) -> Result<Vec<Receiver>, IQError> {
+    let mut src = open_source(config, &config.source, config.get_center(), config.sample_rate)?;
+    if let Some(recording) = &config.raw_recording {
+        src = Box::new(RecordingIQSource::new(src, recording.clone()));
+    }
+    // recordings that know their center frequency only need it overridden if they are wrong
+    let center = config.center
+        .or(src.center_frequency())
+        .unwrap_or_else(|| config.get_center());
+    let mut receivers = vec![Receiver { name: None, src, channels: config.channels.clone(), center }];
+
+    for receiver in &config.receivers {
+        let src = match open_source(config, &receiver.source, receiver.get_center(), receiver.sample_rate) {
+            Ok(src) => src,
+            Err(e) => {
+                // release the radios that did open
+                for opened in receivers {
+                    opened.src.stop();
+                }
+                return Err(e);
+            }
+        };
+        let center = receiver.center
+            .or(src.center_frequency())
+            .unwrap_or_else(|| receiver.get_center());
+        receivers.push(Receiver {
+            name: Some(receiver.name.clone()),
+            src,
+            channels: receiver.channels.clone(),
+            center,
+        });
+    }
+    Ok(receivers)
+}
+
+/// Open the configured sources and start decoding every channel on background threads.
+///
+/// Fails if a receiver has no channels or a source cannot be opened. Errors reading from one later stop it and are returned by
+/// [`DecoderHandle::join`], apart from dropped samples and timeouts which are only logged.
+pub fn start_decoders(config: DecoderConfig, new_packet: impl Fn(ReceivedPacket) + Send + Sync + 'static) -> Result<DecoderHandle, IQError> {
     let start = Instant::now();
-    let args = Arguments::parse();
-    if args.frequencies.is_empty() {
-        panic!("Requires at least one frequency argument");
+    // checked before any radio is opened, so there is nothing to release
+    if config.channels.is_empty() {
+        return Err(IQError::NoChannels(None));
+    }
+    if let Some(receiver) = config.receivers.iter().find(|receiver| receiver.channels.is_empty()) {
+        return Err(IQError::NoChannels(Some(receiver.name.clone())));
     }
 
-    let center = args.get_center();
-    let mut src: Box<dyn IQSource + Send> = if let Some(file_name) = args.file {
-        Box::new(FileIQSource::new(file_name))
-    } else {
-        Box::new(HackRFIQSource::new(center).unwrap())
+    let receivers = open_receivers(&config)?;
+    if let Err(e) = receivers.iter().try_for_each(check_channels) {
+        for receiver in receivers {
+            receiver.src.stop();
+        }
+        return Err(e);
+    }
+    let recorder = match &config.record {
+        Some(path) => {
+            let main = &receivers[0];
+            Some(SigMfWriter::create(path, main.src.sample_rate(), main.center, main.src.hardware().as_deref())?)
+        }
+        None => None,
     };
 
-    let mut bus: Bus<Arc<Vec<Complex<f32>>>> = Bus::new(10000);
-
-
-    std::thread::scope(|scope| {
-        let mut worker_handles = Vec::new();
-        for freq in args.frequencies {
-            let callback_ref = &new_packet;
-            let mut bus_recv = bus.add_rx();
-            let handle = scope.spawn(move || {
-                let mut decoder = StreamingGFSKDecoder::new(HZ, freq - center, BAUD);
-                while let Ok(packet) = bus_recv.recv() {
-                    decoder.feed(&packet, |packet|{
-                        if let Ok(decoded) = decode(&packet) {
-                            callback_ref(freq, decoded);
-                        }
-                    });
-                }
-            });
-            worker_handles.push(handle);
+    // the same packet heard by two receivers is only passed on once
+    let dedupe = (receivers.len() > 1).then(|| Mutex::new(Deduplicator::new()));
+    let new_packet: Arc<dyn Fn(ReceivedPacket) + Send + Sync> = Arc::new(move |packet: ReceivedPacket| {
+        if let Some(dedupe) = &dedupe {
+            if !dedupe.lock().unwrap().is_new(&packet.packet) {
+                return;
+            }
         }
+        new_packet(packet);
+    });
+    let running = Arc::new(AtomicBool::new(true));
+    let (event_sender, events) = mpsc::channel();
+
+    let mut readers = Vec::new();
+    let mut workers = Vec::new();
+    let mut commands = Vec::new();
+    let mut retunes = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut recorder = recorder;
+    for receiver in receivers {
+        let (command_sender, command_receiver) = mpsc::channel();
+        commands.push((receiver.name.clone(), command_sender));
+        let control = ReaderControl {
+            commands: command_receiver,
+            diagnostics: Arc::new(Mutex::new(receiver.src.diagnostics())),
+        };
+        diagnostics.push((receiver.name.clone(), control.diagnostics.clone()));
+        let name = receiver.name.clone();
+        // only the main receiver is recorded
+        let (reader, receiver_workers, tunings) = spawn_receiver(receiver, &config, recorder.take(), &running, &new_packet, &event_sender, control);
+        readers.push(reader);
+        workers.extend(receiver_workers);
+        retunes.push((name, tunings));
+    }
+
+    Ok(DecoderHandle { running, readers, workers, events, commands, retunes, diagnostics, start })
+}
+
+/// Fail if any of `receiver`'s channels would alias at its sample rate.
+fn check_channels(receiver: &Receiver) -> Result<(), IQError> {
+    let max_offset = max_offset(receiver.src.sample_rate());
+    match receiver.channels.iter().find(|channel| (channel.frequency - receiver.center).abs() > max_offset) {
+        Some(channel) => Err(IQError::ChannelOutOfRange(channel.frequency)),
+        None => Ok(()),
+    }
+}
 
-        scope.spawn(move || {
-            loop {
-                let buffer = src.read();
-                if buffer.is_empty() {
-                    return;
+fn spawn_receiver(
+    receiver: Receiver,
+    config: &DecoderConfig,
+    mut recorder: Option<SigMfWriter>,
+    running: &Arc<AtomicBool>,
+    new_packet: &Arc<dyn Fn(ReceivedPacket) + Send + Sync>,
+    event_sender: &mpsc::Sender<DecoderEvent>,
+    control: ReaderControl,
+) -> (Reader, Vec<JoinHandle<()>>, Vec<Tuning>) {
+    let Receiver { name, mut src, channels, center } = receiver;
+    let baud = config.baud;
+    let sample_rate = src.sample_rate();
+    let packet_samples = (TOTAL_PACKET_BITS as f64 * sample_rate / baud) as u64;
+
+    let mut bus: Bus<Arc<SampleBlock>> = Bus::new(config.bus_depth);
+
+    let factor = decimation_factor(sample_rate, baud);
+    let offsets: Vec<f64> = channels.iter().map(|channel| channel.frequency - center).collect();
+    let mut channelizer = Channelizer::new(sample_rate, &offsets, factor);
+    let channel_rate = sample_rate / factor as f64;
+    let reach = retune_range(channel_rate);
+    let max_offset = max_offset(sample_rate);
+
+    let mut workers = Vec::new();
+    let mut senders = Vec::new();
+    let mut tunings = Vec::new();
+    for (mut channel, mut residual) in channels.into_iter().zip(channelizer.residuals()) {
+        // within the channel's share of the filter bank, without aliasing
+        let bin = channel.frequency - residual;
+        let range = (bin - reach).max(center - max_offset)..=(bin + reach).min(center + max_offset);
+        let (retune_sender, retunes) = mpsc::channel();
+        tunings.push(Tuning { sender: retune_sender, range });
+        let callback_ref = new_packet.clone();
+        let (sender, channel_recv) = mpsc::sync_channel::<SampleBlock>(config.bus_depth);
+        senders.push(sender);
+        let annotator = recorder.as_ref().map(SigMfWriter::annotator);
+        let name = name.clone();
+        let handle = std::thread::spawn(move || {
+            let mut decoder = StreamingGFSKDecoder::new(channel_rate, residual, baud);
+            let on_packet = |channel: &Channel, mut packet: Packet| {
+                // back to an index into the source's samples
+                packet.sample_idx *= factor;
+                if let Ok(decoded) = decode(&packet) {
+                    if let Some(annotator) = &annotator {
+                        annotator.annotate(packet.sample_idx as u64, packet_samples, channel.frequency, &decoded);
+                    }
+                    callback_ref(ReceivedPacket::new(name.as_deref(), channel, &packet, decoded));
                 }
-                bus.broadcast(Arc::new(buffer));
+            };
+            // recv only fails once the channelizer has finished and everything queued was consumed
+            while let Ok(block) = channel_recv.recv() {
+                for frequency in retunes.try_iter() {
+                    residual += frequency - channel.frequency;
+                    channel.frequency = frequency;
+                    decoder.retune(residual);
+                }
+                if block.discontinuity {
+                    decoder.restart(|packet| on_packet(&channel, packet));
+                }
+                decoder.feed(&block.samples, |packet| on_packet(&channel, packet));
             }
+            decoder.finish(|packet| on_packet(&channel, packet));
         });
+        workers.push(handle);
+    }
+
+    // one pass over the source's samples splits out every channel
+    let mut bus_recv = bus.add_rx();
+    workers.push(std::thread::spawn(move || {
+        let mut outputs = vec![Vec::new(); senders.len()];
+        let send = |outputs: &mut [Vec<Complex<f32>>], discontinuity: bool| {
+            for (sender, samples) in senders.iter().zip(outputs.iter_mut()) {
+                let samples = std::mem::take(samples);
+                if !samples.is_empty() || discontinuity {
+                    // a worker only goes away if it panicked
+                    let _ = sender.send(SampleBlock { samples, discontinuity });
+                }
+            }
+        };
+        while let Ok(block) = bus_recv.recv() {
+            if block.discontinuity {
+                // what came before the gap is decoded before the decoders restart
+                channelizer.reset(&mut outputs);
+                send(&mut outputs, false);
+            }
+            channelizer.process(&block.samples, &mut outputs);
+            send(&mut outputs, block.discontinuity);
+        }
+        channelizer.flush(&mut outputs);
+        send(&mut outputs, false);
+    }));
 
-        worker_handles.into_iter().for_each(|handle| handle.join().unwrap());
+    let event_sender = event_sender.clone();
+    let reader_running = running.clone();
+    let reader = std::thread::spawn(move || {
+        let mut result = Ok(());
+        let mut discontinuity = false;
+        let mut diagnosed = Instant::now();
+        while reader_running.load(Ordering::Relaxed) {
+            let mut changed = false;
+            for command in control.commands.try_iter() {
+                changed = true;
+                if let Err(e) = src.command(command) {
+                    eprintln!("Failed to change source: {e}");
+                }
+            }
+            let read = src.read();
+            while let Some(event) = src.poll_event() {
+                changed = true;
+                discontinuity |= event.is_discontinuity();
+                // nobody may be listening, which is fine
+                let _ = event_sender.send(DecoderEvent { source: name.clone(), event });
+            }
+            // everything but the longest gap between transfers changes with an event or command,
+            // and that only needs to be roughly up to date
+            if changed || diagnosed.elapsed() >= DIAGNOSTICS_INTERVAL {
+                *control.diagnostics.lock().unwrap() = src.diagnostics();
+                diagnosed = Instant::now();
+            }
+            let buffer = match read {
+                Ok(buffer) => buffer,
+                Err(IQError::Eof) => break,
+                // the source is waiting on something, e.g. a HackRF to be plugged back in
+                Err(IQError::Timeout) => continue,
+                Err(e) if e.is_transient() => {
+                    eprintln!("Missed samples: {e}");
+                    discontinuity |= matches!(e, IQError::Overflow);
+                    continue;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            if let Some(writer) = &mut recorder {
+                if let Err(e) = writer.write_samples(&buffer) {
+                    eprintln!("Stopped recording: {e}");
+                    recorder = None;
+                }
+            }
+            bus.broadcast(Arc::new(SampleBlock { samples: buffer, discontinuity }));
+            discontinuity = false;
+        }
+        src.stop();
+        // the decoders run dry and drop their annotators once nothing more is broadcast
+        drop(bus);
+        if let Some(writer) = recorder {
+            if let Err(e) = writer.finish() {
+                eprintln!("Failed to finish recording: {e}");
+            }
+        }
+        result
     });
 
-    let end = Instant::now();
-    println!("Took {:?}", end - start);
+    (reader, workers, tunings)
 }
@@ -0,0 +1,48 @@
+use std::f64::consts::PI;
+use num_complex::Complex;
+
+/// One whole cycle of the phase accumulator.
+const CYCLE: f64 = 18_446_744_073_709_551_616.0; // 2^64
+
+/// Numerically controlled oscillator that mixes samples up or down in frequency.
+///
+/// The phase is a 64-bit accumulator in fractions of a cycle, so any frequency is hit to within a
+/// tiny fraction of a hertz and the phase carries on exactly from one buffer to the next, even
+/// across a change of frequency.
+pub struct Nco {
+    sample_rate: f64,
+    phase: u64,
+    step: u64,
+}
+
+impl Nco {
+    /// Shift samples at `sample_rate` by `frequency` Hz, which may be negative.
+    pub fn new(frequency: f64, sample_rate: f64) -> Nco {
+        let mut nco = Nco { sample_rate, phase: 0, step: 0 };
+        nco.set_frequency(frequency);
+        nco
+    }
+
+    /// Change frequency without a jump in phase.
+    pub fn set_frequency(&mut self, frequency: f64) {
+        // the fraction of a cycle per sample, wrapped into the accumulator's range
+        self.step = ((frequency / self.sample_rate).fract() * CYCLE) as i128 as u64;
+    }
+
+    /// Multiply `x` by the oscillator, continuing from where the last call left off.
+    pub fn shift(&mut self, x: &mut [Complex<f32>]) {
+        // a rotator is much cheaper than a sin and cos per sample, and starting it afresh from the
+        // accumulator each time keeps its rounding errors from building up
+        let step = phasor(self.step);
+        let mut rotation = phasor(self.phase);
+        for x in x.iter_mut() {
+            *x *= Complex::new(rotation.re as f32, rotation.im as f32);
+            rotation *= step;
+        }
+        self.phase = self.phase.wrapping_add(self.step.wrapping_mul(x.len() as u64));
+    }
+}
+
+fn phasor(phase: u64) -> Complex<f64> {
+    Complex::from_polar(1.0, 2.0 * PI * phase as f64 / CYCLE)
+}
@@ -0,0 +1,67 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use num_complex::Complex;
+use crate::{Diagnostics, IQError, IQSource, SourceCommand, SourceEvent};
+
+/// Wraps a recording so that its samples come out no faster than they were recorded, optionally
+/// sped up or slowed down, for replaying a flight into the GUI as it happened.
+pub struct PacedIQSource<S> {
+    inner: S,
+    speed: f64,
+    started: Option<Instant>,
+    samples: u64,
+}
+
+impl<S: IQSource> PacedIQSource<S> {
+    /// `speed` is relative to real time, so 2.0 plays a recording back in half the time it took.
+    /// It has to be positive.
+    pub fn new(inner: S, speed: f64) -> Result<PacedIQSource<S>, IQError> {
+        if !(speed > 0.0 && speed.is_finite()) {
+            return Err(IQError::PlaybackSpeed(speed));
+        }
+        Ok(PacedIQSource { inner, speed, started: None, samples: 0 })
+    }
+}
+
+impl<S: IQSource> IQSource for PacedIQSource<S> {
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        let buffer = self.inner.read()?;
+        let started = *self.started.get_or_insert_with(Instant::now);
+
+        // hand the buffer over when its last sample would have arrived from a radio
+        self.samples += buffer.len() as u64;
+        let due = Duration::from_secs_f64(self.samples as f64 / (self.inner.sample_rate() * self.speed));
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(buffer)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.inner.sample_rate()
+    }
+
+    fn center_frequency(&self) -> Option<f64> {
+        self.inner.center_frequency()
+    }
+
+    fn poll_event(&mut self) -> Option<SourceEvent> {
+        self.inner.poll_event()
+    }
+
+    fn command(&mut self, command: SourceCommand) -> Result<(), IQError> {
+        self.inner.command(command)
+    }
+
+    fn diagnostics(&self) -> Option<Diagnostics> {
+        self.inner.diagnostics()
+    }
+
+    fn hardware(&self) -> Option<String> {
+        self.inner.hardware()
+    }
+
+    fn stop(self: Box<Self>) {
+        Box::new(self.inner).stop()
+    }
+}
@@ -2,5 +2,19 @@
 #[repr(C)]
 pub struct Packet {
     pub crc_match: bool,
-    pub data: [u8; 34]
+    pub data: [u8; 34],
+    /// Index of the first sample of the packet in the decoder's input stream.
+    pub sample_idx: usize,
+    /// Mean power over the packet in dB relative to a full scale 8-bit sample.
+    pub rssi: f32,
+    /// Packet power relative to the decoder's running noise estimate in dB.
+    pub snr: f32,
+    /// Offset of the carrier from the channel frequency in Hz.
+    pub freq_error: f32,
+}
+
+impl Packet {
+    pub fn new(crc_match: bool, data: [u8; 34]) -> Packet {
+        Packet { crc_match, data, sample_idx: 0, rssi: 0.0, snr: 0.0, freq_error: 0.0 }
+    }
 }
@@ -25,6 +25,40 @@ impl DecodedPacket {
             DecodedPacket::UnknownPacket(packet) => packet.crc,
         }
     }
+
+    pub fn serial(&self) -> u16 {
+        match self {
+            DecodedPacket::SensorPacket(packet) => packet.serial,
+            DecodedPacket::ConfigPacket(packet) => packet.serial,
+            DecodedPacket::GPSPacket(packet) => packet.serial,
+            DecodedPacket::SatellitePacket(packet) => packet.serial,
+            DecodedPacket::KalmanVoltagePacket(packet) => packet.serial,
+            DecodedPacket::UnknownPacket(packet) => packet.serial,
+        }
+    }
+
+    /// Flight computer clock in seconds, which wraps every 655.36 s.
+    pub fn tick(&self) -> f64 {
+        match self {
+            DecodedPacket::SensorPacket(packet) => packet.tick,
+            DecodedPacket::ConfigPacket(packet) => packet.tick,
+            DecodedPacket::GPSPacket(packet) => packet.tick,
+            DecodedPacket::SatellitePacket(packet) => packet.tick,
+            DecodedPacket::KalmanVoltagePacket(packet) => packet.tick,
+            DecodedPacket::UnknownPacket(packet) => packet.tick,
+        }
+    }
+
+    pub fn ptype(&self) -> u8 {
+        match self {
+            DecodedPacket::SensorPacket(packet) => packet.ptype,
+            DecodedPacket::ConfigPacket(packet) => packet.ptype,
+            DecodedPacket::GPSPacket(packet) => packet.ptype,
+            DecodedPacket::SatellitePacket(packet) => packet.ptype,
+            DecodedPacket::KalmanVoltagePacket(packet) => packet.ptype,
+            DecodedPacket::UnknownPacket(packet) => packet.ptype,
+        }
+    }
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -0,0 +1,40 @@
+use std::time::SystemTime;
+use serde::Serialize;
+use crate::iq_source::unix_millis;
+use crate::{Channel, DecodedPacket, Packet};
+
+/// A decoded packet together with where, when and how well it was received.
+#[derive(Serialize, Clone, Debug)]
+pub struct ReceivedPacket {
+    /// Wall clock time the packet was decoded, in milliseconds since the Unix epoch.
+    pub received_at: u64,
+    /// Index of the packet's first sample in the source's sample stream.
+    pub sample_index: u64,
+    /// Name of the receiver that heard the packet, `None` for the main one.
+    pub source: Option<String>,
+    /// Frequency of the channel the packet was heard on.
+    pub frequency: f64,
+    pub label: Option<String>,
+    /// Mean packet power in dB relative to full scale.
+    pub rssi: f32,
+    pub snr: f32,
+    /// Offset of the transmitter from the channel frequency in Hz.
+    pub freq_error: f32,
+    pub packet: DecodedPacket,
+}
+
+impl ReceivedPacket {
+    pub(crate) fn new(source: Option<&str>, channel: &Channel, raw: &Packet, packet: DecodedPacket) -> ReceivedPacket {
+        ReceivedPacket {
+            received_at: unix_millis(SystemTime::now()),
+            sample_index: raw.sample_idx as u64,
+            source: source.map(str::to_string),
+            frequency: channel.frequency,
+            label: channel.label.clone(),
+            rssi: raw.rssi,
+            snr: raw.snr,
+            freq_error: raw.freq_error,
+            packet,
+        }
+    }
+}
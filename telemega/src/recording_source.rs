@@ -0,0 +1,340 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use num_complex::Complex;
+use serde::Serialize;
+use crate::gain::GainSettings;
+use crate::iq_source::{Diagnostics, SourceCommand, SourceEvent};
+use crate::sigmf::{iso8601, to_i8};
+use crate::{IQError, IQSource};
+
+/// Where and how much a [`RecordingIQSource`] writes.
+#[derive(Clone, Debug)]
+pub struct RawRecording {
+    base_path: PathBuf,
+    max_file_bytes: Option<u64>,
+    max_file_duration: Option<Duration>,
+    queue_depth: usize,
+}
+
+impl RawRecording {
+    /// Record to `<base_path>-000.cs8`, `<base_path>-001.cs8`, ... each with a `.json` sidecar.
+    pub fn new(base_path: impl Into<PathBuf>) -> RawRecording {
+        RawRecording {
+            base_path: base_path.into(),
+            max_file_bytes: None,
+            max_file_duration: None,
+            queue_depth: 64,
+        }
+    }
+
+    /// Start a new file once the current one would grow past `bytes`.
+    pub fn max_file_bytes(mut self, bytes: u64) -> RawRecording {
+        self.max_file_bytes = Some(bytes);
+        self
+    }
+
+    /// Start a new file once the current one holds `duration` worth of samples.
+    pub fn max_file_duration(mut self, duration: Duration) -> RawRecording {
+        self.max_file_duration = Some(duration);
+        self
+    }
+
+    /// Number of buffers that may wait for the disk before new ones are dropped from the recording.
+    pub fn queue_depth(mut self, depth: usize) -> RawRecording {
+        self.queue_depth = depth;
+        self
+    }
+}
+
+enum Record {
+    Samples { start: u64, data: Vec<u8> },
+    Gain { sample: u64, gain: GainSettings },
+}
+
+/// Tees the samples read from another source into rotating cs8 files on a background thread.
+///
+/// The decoder never waits on the disk: if the writer falls behind by more than the queue depth,
+/// buffers are left out of the recording and the gap is noted in the sidecar instead.
+pub struct RecordingIQSource<S> {
+    inner: S,
+    // None once the writer thread has given up
+    sender: Option<SyncSender<Record>>,
+    writer: Option<JoinHandle<()>>,
+    samples: u64,
+    gain: Option<GainSettings>,
+    // a gain change that did not fit in the queue yet, with the sample it took effect at
+    pending_gain: Option<(u64, GainSettings)>,
+    events: VecDeque<SourceEvent>,
+}
+
+impl<S: IQSource> RecordingIQSource<S> {
+    pub fn new(inner: S, recording: RawRecording) -> RecordingIQSource<S> {
+        let (sender, receiver) = mpsc::sync_channel(recording.queue_depth);
+        let gain = inner.gain();
+        let writer = RawWriter {
+            limits: recording.clone(),
+            sample_rate: inner.sample_rate(),
+            center: inner.center_frequency(),
+            gain,
+            file_index: 0,
+            current: None,
+        };
+        let writer = thread::spawn(move || writer.run(receiver));
+
+        RecordingIQSource {
+            inner,
+            sender: Some(sender),
+            writer: Some(writer),
+            samples: 0,
+            gain,
+            pending_gain: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Queue a record for the writer, returning false if the queue is full.
+    fn send(&mut self, record: Record) -> bool {
+        let Some(sender) = &self.sender else { return true; };
+        match sender.try_send(record) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => false,
+            Err(TrySendError::Disconnected(_)) => {
+                self.sender = None;
+                true
+            }
+        }
+    }
+}
+
+impl<S: IQSource> IQSource for RecordingIQSource<S> {
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        let read = self.inner.read();
+        // samples lost to a dropout come before the buffer read after it, and show up as a gap
+        while let Some(event) = self.inner.poll_event() {
+            if let SourceEvent::Reconnected { samples_lost, .. } = event {
+                self.samples += samples_lost;
+            }
+            self.events.push_back(event);
+        }
+        let buffer = read?;
+
+        let gain = self.inner.gain();
+        if gain != self.gain {
+            if let Some(gain) = gain {
+                self.pending_gain = Some((self.samples, gain));
+            }
+            self.gain = gain;
+        }
+        if let Some((sample, gain)) = self.pending_gain {
+            if self.send(Record::Gain { sample, gain }) {
+                self.pending_gain = None;
+            }
+        }
+
+        let data = buffer.iter()
+            .flat_map(|s| [to_i8(s.re) as u8, to_i8(s.im) as u8])
+            .collect();
+        self.send(Record::Samples { start: self.samples, data });
+        self.samples += buffer.len() as u64;
+        Ok(buffer)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.inner.sample_rate()
+    }
+
+    fn center_frequency(&self) -> Option<f64> {
+        self.inner.center_frequency()
+    }
+
+    fn gain(&self) -> Option<GainSettings> {
+        self.inner.gain()
+    }
+
+    fn poll_event(&mut self) -> Option<SourceEvent> {
+        self.events.pop_front()
+    }
+
+    fn command(&mut self, command: SourceCommand) -> Result<(), IQError> {
+        self.inner.command(command)
+    }
+
+    fn diagnostics(&self) -> Option<Diagnostics> {
+        self.inner.diagnostics()
+    }
+
+    fn hardware(&self) -> Option<String> {
+        self.inner.hardware()
+    }
+
+    fn stop(mut self: Box<Self>) {
+        // closing the channel lets the writer finish what is queued and exit
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                eprintln!("Raw recording writer panicked");
+            }
+        }
+        Box::new(self.inner).stop()
+    }
+}
+
+#[derive(Serialize)]
+struct GainChange {
+    /// Offset into this file.
+    sample: u64,
+    #[serde(flatten)]
+    gain: GainSettings,
+}
+
+#[derive(Serialize)]
+struct Gap {
+    /// Offset into this file of the first sample after the gap.
+    sample: u64,
+    missing: u64,
+}
+
+#[derive(Serialize)]
+struct Sidecar {
+    format: &'static str,
+    sample_rate: f64,
+    center_frequency: Option<f64>,
+    start_time: String,
+    /// Index of this file's first sample in the whole session.
+    first_sample: u64,
+    samples: u64,
+    gain: Option<GainSettings>,
+    gain_changes: Vec<GainChange>,
+    gaps: Vec<Gap>,
+}
+
+struct RawFile {
+    data: BufWriter<File>,
+    sidecar_path: PathBuf,
+    sidecar: Sidecar,
+}
+
+impl RawFile {
+    fn write_sidecar(&self) -> Result<(), Error> {
+        let file = BufWriter::new(File::create(&self.sidecar_path)?);
+        serde_json::to_writer_pretty(file, &self.sidecar)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        self.data.flush()?;
+        self.write_sidecar()
+    }
+}
+
+struct RawWriter {
+    limits: RawRecording,
+    sample_rate: f64,
+    center: Option<f64>,
+    gain: Option<GainSettings>,
+    file_index: usize,
+    current: Option<RawFile>,
+}
+
+impl RawWriter {
+    fn run(mut self, receiver: Receiver<Record>) {
+        let mut next_sample = 0;
+        while let Ok(record) = receiver.recv() {
+            let result = match record {
+                Record::Gain { sample, gain } => self.gain_changed(sample, gain),
+                Record::Samples { start, data } => {
+                    let written = self.write(next_sample, start, &data);
+                    next_sample = start + (data.len() / 2) as u64;
+                    written
+                }
+            };
+            if let Err(e) = result {
+                // dropping the receiver tells the source to stop sending
+                eprintln!("Stopped raw recording: {e}");
+                break;
+            }
+        }
+
+        if let Some(file) = self.current.take() {
+            if let Err(e) = file.finish() {
+                eprintln!("Failed to finish raw recording: {e}");
+            }
+        }
+    }
+
+    fn path(&self, extension: &str) -> PathBuf {
+        let base: &Path = &self.limits.base_path;
+        let mut name = base.as_os_str().to_owned();
+        name.push(format!("-{:03}.{extension}", self.file_index));
+        PathBuf::from(name)
+    }
+
+    fn is_full(&self, file: &RawFile, new_samples: u64) -> bool {
+        let samples = file.sidecar.samples + new_samples;
+        let too_big = self.limits.max_file_bytes.is_some_and(|max| samples * 2 > max);
+        let too_long = self.limits.max_file_duration
+            .is_some_and(|max| samples as f64 / self.sample_rate > max.as_secs_f64());
+        // a single buffer always goes somewhere, even if it is over the limit by itself
+        file.sidecar.samples > 0 && (too_big || too_long)
+    }
+
+    fn write(&mut self, expected: u64, start: u64, data: &[u8]) -> Result<(), Error> {
+        let new_samples = (data.len() / 2) as u64;
+        if self.current.as_ref().is_some_and(|file| self.is_full(file, new_samples)) {
+            if let Some(file) = self.current.take() {
+                file.finish()?;
+            }
+            self.file_index += 1;
+        }
+
+        match &mut self.current {
+            Some(file) => {
+                if start > expected {
+                    file.sidecar.gaps.push(Gap { sample: file.sidecar.samples, missing: start - expected });
+                    file.write_sidecar()?;
+                }
+            }
+            None => {
+                let sidecar = Sidecar {
+                    format: "cs8",
+                    sample_rate: self.sample_rate,
+                    center_frequency: self.center,
+                    start_time: iso8601(SystemTime::now()),
+                    first_sample: start,
+                    samples: 0,
+                    gain: self.gain,
+                    gain_changes: Vec::new(),
+                    gaps: Vec::new(),
+                };
+                let file = RawFile {
+                    data: BufWriter::new(File::create(self.path("cs8"))?),
+                    sidecar_path: self.path("json"),
+                    sidecar,
+                };
+                file.write_sidecar()?;
+                self.current = Some(file);
+            }
+        }
+
+        if let Some(file) = &mut self.current {
+            file.data.write_all(data)?;
+            file.sidecar.samples += new_samples;
+        }
+        Ok(())
+    }
+
+    fn gain_changed(&mut self, sample: u64, gain: GainSettings) -> Result<(), Error> {
+        self.gain = Some(gain);
+        if let Some(file) = &mut self.current {
+            let offset = sample.saturating_sub(file.sidecar.first_sample);
+            file.sidecar.gain_changes.push(GainChange { sample: offset, gain });
+            file.write_sidecar()?;
+        }
+        Ok(())
+    }
+}
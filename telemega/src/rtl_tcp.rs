@@ -0,0 +1,231 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use num_complex::Complex;
+use crate::sample_format::SampleFormat;
+use crate::{IQError, IQSource};
+
+/// Sample rate rtl_tcp servers are asked for when none is configured, the highest an RTL-SDR
+/// manages reliably.
+pub const DEFAULT_RTL_SAMPLE_RATE: f64 = 2_400_000.0;
+
+const MAGIC: &[u8; 4] = b"RTL0";
+const BUF_SIZE: usize = 256 * 1024;
+/// How long to wait for the server to answer before giving up on it.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a read waits for samples before returning [`IQError::Timeout`], so that a link that
+/// drops without closing does not stop the reader from seeing it has been asked to stop.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[repr(u8)]
+enum Command {
+    Frequency = 0x01,
+    SampleRate = 0x02,
+    GainMode = 0x03,
+    Gain = 0x04,
+    AgcMode = 0x08,
+}
+
+/// Receives unsigned 8-bit samples from a remote RTL-SDR over the `rtl_tcp` protocol, so the
+/// receiver can sit on a mast while decoding happens elsewhere.
+pub struct RtlTcpIQSource {
+    stream: TcpStream,
+    sample_rate: f64,
+    center: f64,
+    tuner_type: u32,
+    gain_count: u32,
+    buf: Vec<u8>,
+    // bytes of a sample split across two reads
+    partial: usize,
+}
+
+impl RtlTcpIQSource {
+    /// Connect to an rtl_tcp server and tune it. Without a gain in dB the tuner's AGC is used.
+    pub fn connect(address: impl ToSocketAddrs, center: f64, sample_rate: f64, gain: Option<f64>) -> Result<RtlTcpIQSource, Error> {
+        let mut stream = connect_timeout(address)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let mut header = [0u8; 12];
+        stream.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not an rtl_tcp server"));
+        }
+
+        let mut source = RtlTcpIQSource {
+            stream,
+            sample_rate,
+            center,
+            tuner_type: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            gain_count: u32::from_be_bytes([header[8], header[9], header[10], header[11]]),
+            buf: vec![0; BUF_SIZE],
+            partial: 0,
+        };
+        source.set_sample_rate(sample_rate)?;
+        source.set_frequency(center)?;
+        source.set_gain(gain)?;
+        Ok(source)
+    }
+
+    fn command(&mut self, command: Command, param: u32) -> Result<(), Error> {
+        let mut message = [0u8; 5];
+        message[0] = command as u8;
+        message[1..].copy_from_slice(&param.to_be_bytes());
+        self.stream.write_all(&message)
+    }
+
+    pub fn set_frequency(&mut self, hz: f64) -> Result<(), Error> {
+        self.command(Command::Frequency, hz as u32)?;
+        self.center = hz;
+        Ok(())
+    }
+
+    pub fn set_sample_rate(&mut self, hz: f64) -> Result<(), Error> {
+        self.command(Command::SampleRate, hz as u32)?;
+        self.sample_rate = hz;
+        Ok(())
+    }
+
+    /// Set a manual tuner gain in dB, or hand gain control to the tuner's AGC with `None`.
+    pub fn set_gain(&mut self, gain: Option<f64>) -> Result<(), Error> {
+        match gain {
+            Some(db) => {
+                self.command(Command::AgcMode, 0)?;
+                self.command(Command::GainMode, 1)?;
+                // the protocol takes tenths of a dB
+                self.command(Command::Gain, (db * 10.0).round() as u32)
+            }
+            None => {
+                self.command(Command::GainMode, 0)?;
+                self.command(Command::AgcMode, 1)
+            }
+        }
+    }
+
+    /// Tuner chip reported by the server, e.g. 5 for an R820T.
+    pub fn tuner_type(&self) -> u32 {
+        self.tuner_type
+    }
+
+    /// Number of discrete gain steps the tuner supports.
+    pub fn gain_count(&self) -> u32 {
+        self.gain_count
+    }
+}
+
+/// Connect to the first of `address`'s addresses that answers in time, as `TcpStream::connect`
+/// does without a timeout.
+fn connect_timeout(address: impl ToSocketAddrs) -> Result<TcpStream, Error> {
+    let mut last_error = Error::new(ErrorKind::InvalidInput, "address resolved to nothing");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+impl IQSource for RtlTcpIQSource {
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        let mut out = Vec::new();
+        while out.is_empty() {
+            match self.stream.read(&mut self.buf[self.partial..]) {
+                // the server only stops sending when it goes away
+                Ok(0) => return Err(IQError::Disconnected),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                // a read timeout comes back as WouldBlock or TimedOut depending on the platform,
+                // both of which are IQError::Timeout
+                Err(e) => return Err(e.into()),
+                Ok(ct) => {
+                    let len = self.partial + ct;
+                    let used = SampleFormat::Cu8.convert(&self.buf[..len], &mut out);
+                    self.buf.copy_within(used..len, 0);
+                    self.partial = len - used;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn center_frequency(&self) -> Option<f64> {
+        Some(self.center)
+    }
+
+    fn hardware(&self) -> Option<String> {
+        Some("RTL-SDR".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use super::*;
+
+    /// Serve an R820T's header followed by `samples` to one client, passing back the first `commands`
+    /// commands it sends before any samples go out. The connection is held open for `linger`.
+    fn serve(samples: Vec<u8>, commands: usize, linger: Duration) -> (String, mpsc::Receiver<(u8, u32)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = Vec::from(*MAGIC);
+            header.extend_from_slice(&5u32.to_be_bytes());
+            header.extend_from_slice(&29u32.to_be_bytes());
+            stream.write_all(&header).unwrap();
+            for _ in 0..commands {
+                let mut message = [0u8; 5];
+                stream.read_exact(&mut message).unwrap();
+                let param = u32::from_be_bytes([message[1], message[2], message[3], message[4]]);
+                sender.send((message[0], param)).unwrap();
+            }
+            stream.write_all(&samples).unwrap();
+            thread::sleep(linger);
+        });
+        (address, received)
+    }
+
+    #[test]
+    fn tunes_and_reads_samples() {
+        let (address, commands) = serve(vec![255, 0, 128, 127, 0, 255], 4, Duration::ZERO);
+        let mut source = RtlTcpIQSource::connect(address, 436_850_000.0, 2_400_000.0, None).unwrap();
+        assert_eq!(source.tuner_type(), 5);
+        assert_eq!(source.gain_count(), 29);
+        let commands: Vec<(u8, u32)> = commands.iter().collect();
+        assert_eq!(commands, [
+            (Command::SampleRate as u8, 2_400_000),
+            (Command::Frequency as u8, 436_850_000),
+            (Command::GainMode as u8, 0),
+            (Command::AgcMode as u8, 1),
+        ]);
+
+        let mut samples = Vec::new();
+        while samples.len() < 3 {
+            samples.extend(source.read().unwrap());
+        }
+        assert_eq!(samples, [Complex::new(127.5, -127.5), Complex::new(0.5, -0.5), Complex::new(-127.5, 127.5)]);
+        assert!(matches!(source.read(), Err(IQError::Disconnected)));
+    }
+
+    #[test]
+    fn manual_gain_in_tenths_of_a_db() {
+        let (address, commands) = serve(Vec::new(), 5, Duration::ZERO);
+        RtlTcpIQSource::connect(address, 436_850_000.0, 2_400_000.0, Some(29.7)).unwrap();
+        let commands: Vec<(u8, u32)> = commands.iter().skip(2).collect();
+        assert_eq!(commands, [(Command::AgcMode as u8, 0), (Command::GainMode as u8, 1), (Command::Gain as u8, 297)]);
+    }
+
+    #[test]
+    fn silent_server_times_out() {
+        let (address, _commands) = serve(Vec::new(), 4, READ_TIMEOUT * 3);
+        let mut source = RtlTcpIQSource::connect(address, 436_850_000.0, 2_400_000.0, None).unwrap();
+        assert!(matches!(source.read(), Err(IQError::Timeout)));
+    }
+}
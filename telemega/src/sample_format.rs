@@ -0,0 +1,103 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use num_complex::Complex;
+
+/// Binary layout of interleaved IQ samples in a recording.
+///
+/// Every format is converted to the scale of a signed 8-bit HackRF sample, so a full scale
+/// sample has a magnitude of about 128 regardless of where it came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Signed 8-bit, as written by `hackrf_transfer`.
+    Cs8,
+    /// Unsigned 8-bit offset by 127.5, as written by `rtl_sdr`.
+    Cu8,
+    /// Signed 16-bit little endian, as written by Airspy and SDRplay tools.
+    Cs16,
+    /// 32-bit little endian floats, as written by GNU Radio file sinks.
+    Cf32,
+}
+
+impl SampleFormat {
+    /// Size of one complex sample in bytes.
+    pub fn sample_size(self) -> usize {
+        match self {
+            SampleFormat::Cs8 | SampleFormat::Cu8 => 2,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cf32 => 8,
+        }
+    }
+
+    /// Guess the format from the conventional file extensions of common SDR tools.
+    pub fn from_extension(path: impl AsRef<Path>) -> Option<SampleFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "cs8" | "s8" | "sc8" => Some(SampleFormat::Cs8),
+            "cu8" | "u8" | "cu8iq" => Some(SampleFormat::Cu8),
+            "cs16" | "s16" | "sc16" => Some(SampleFormat::Cs16),
+            "cf32" | "fc32" | "cfile" => Some(SampleFormat::Cf32),
+            _ => None,
+        }
+    }
+
+    /// Convert as many whole samples as `bytes` holds, returning how many bytes were used.
+    pub(crate) fn convert(self, bytes: &[u8], out: &mut Vec<Complex<f32>>) -> usize {
+        let used = bytes.len() - bytes.len() % self.sample_size();
+        let bytes = &bytes[..used];
+        out.reserve(used / self.sample_size());
+        match self {
+            SampleFormat::Cs8 => {
+                out.extend(bytes.chunks_exact(2).map(|s| {
+                    Complex::new(s[0] as i8 as f32, s[1] as i8 as f32)
+                }));
+            }
+            SampleFormat::Cu8 => {
+                out.extend(bytes.chunks_exact(2).map(|s| {
+                    Complex::new(s[0] as f32 - 127.5, s[1] as f32 - 127.5)
+                }));
+            }
+            SampleFormat::Cs16 => {
+                out.extend(bytes.chunks_exact(4).map(|s| {
+                    let i = i16::from_le_bytes([s[0], s[1]]);
+                    let q = i16::from_le_bytes([s[2], s[3]]);
+                    Complex::new(i as f32 / 256.0, q as f32 / 256.0)
+                }));
+            }
+            SampleFormat::Cf32 => {
+                out.extend(bytes.chunks_exact(8).map(|s| {
+                    let i = f32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+                    let q = f32::from_le_bytes([s[4], s[5], s[6], s[7]]);
+                    Complex::new(i * 128.0, q * 128.0)
+                }));
+            }
+        }
+        used
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SampleFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "cs8" => Ok(SampleFormat::Cs8),
+            "cu8" => Ok(SampleFormat::Cu8),
+            "cs16" => Ok(SampleFormat::Cs16),
+            "cf32" => Ok(SampleFormat::Cf32),
+            _ => Err(format!("unknown sample format {s}, expected one of cs8, cu8, cs16, cf32")),
+        }
+    }
+}
+
+impl Display for SampleFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SampleFormat::Cs8 => "cs8",
+            SampleFormat::Cu8 => "cu8",
+            SampleFormat::Cs16 => "cs16",
+            SampleFormat::Cf32 => "cf32",
+        };
+        f.write_str(name)
+    }
+}
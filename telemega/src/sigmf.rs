@@ -0,0 +1,246 @@
+//! Reading and writing [SigMF](https://sigmf.org) recordings.
+//!
+//! A recording is a pair of files sharing a base name: `<name>.sigmf-data` with the raw samples
+//! and `<name>.sigmf-meta` with JSON describing them.
+
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use num_complex::Complex;
+use serde_json::{json, Value};
+use crate::sample_format::SampleFormat;
+use crate::DecodedPacket;
+
+const DATA_EXTENSION: &str = "sigmf-data";
+const META_EXTENSION: &str = "sigmf-meta";
+
+/// Whether `path` names either half of a SigMF recording.
+pub(crate) fn is_sigmf(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext == DATA_EXTENSION || ext == META_EXTENSION)
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    // the base name may itself contain dots, so only strip a SigMF extension
+    if is_sigmf(path) {
+        path.with_extension(extension)
+    } else {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(extension);
+        PathBuf::from(name)
+    }
+}
+
+fn datatype_format(datatype: &str) -> Option<SampleFormat> {
+    match datatype {
+        "ci8" => Some(SampleFormat::Cs8),
+        "cu8" => Some(SampleFormat::Cu8),
+        "ci16_le" => Some(SampleFormat::Cs16),
+        "cf32_le" => Some(SampleFormat::Cf32),
+        _ => None,
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// The parts of a `.sigmf-meta` file needed to play its samples back.
+pub(crate) struct SigMfMeta {
+    pub data_path: PathBuf,
+    pub format: SampleFormat,
+    pub sample_rate: f64,
+    pub center: Option<f64>,
+}
+
+pub(crate) fn read_meta(path: &Path) -> Result<SigMfMeta, Error> {
+    let meta: Value = serde_json::from_reader(File::open(with_extension(path, META_EXTENSION))?)?;
+    let global = &meta["global"];
+
+    let datatype = global["core:datatype"].as_str()
+        .ok_or_else(|| invalid("SigMF metadata has no core:datatype".into()))?;
+    let format = datatype_format(datatype)
+        .ok_or_else(|| invalid(format!("unsupported SigMF datatype {datatype}")))?;
+    let sample_rate = global["core:sample_rate"].as_f64()
+        .ok_or_else(|| invalid("SigMF metadata has no core:sample_rate".into()))?;
+    // only the first capture's frequency is used, retuning mid recording is not supported
+    let center = meta["captures"][0]["core:frequency"].as_f64();
+
+    Ok(SigMfMeta {
+        data_path: with_extension(path, DATA_EXTENSION),
+        format,
+        sample_rate,
+        center,
+    })
+}
+
+/// How often the metadata is rewritten while packets are being annotated, so that it is still
+/// useful if the program does not shut down cleanly.
+const META_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Writes samples as a `ci8` SigMF recording, annotated with the packets decoded from them.
+///
+/// The metadata is written by a thread of its own, so annotating a packet never waits on the disk
+/// and never holds up the samples.
+pub struct SigMfWriter {
+    data: BufWriter<File>,
+    annotations: mpsc::Sender<Value>,
+    meta: JoinHandle<Result<(), Error>>,
+}
+
+/// Marks packets in a [`SigMfWriter`]'s recording, from whichever thread decoded them.
+#[derive(Clone)]
+pub struct SigMfAnnotator {
+    annotations: mpsc::Sender<Value>,
+}
+
+impl SigMfWriter {
+    /// Start a recording at `path`, which may either be a base name or end in one of the SigMF
+    /// extensions. `hw` describes the radio the samples come from, if known.
+    pub fn create(path: impl AsRef<Path>, sample_rate: f64, center: f64, hw: Option<&str>) -> Result<SigMfWriter, Error> {
+        let path = path.as_ref();
+        let meta = Meta {
+            path: with_extension(path, META_EXTENSION),
+            sample_rate,
+            center,
+            hw: hw.map(str::to_string),
+            datetime: iso8601(SystemTime::now()),
+            annotations: Vec::new(),
+        };
+        meta.write()?;
+        let data = BufWriter::new(File::create(with_extension(path, DATA_EXTENSION))?);
+        let (annotations, receiver) = mpsc::channel();
+        Ok(SigMfWriter {
+            data,
+            annotations,
+            meta: thread::spawn(move || meta.write_periodically(receiver)),
+        })
+    }
+
+    /// Something to mark packets with, which may be used after the samples are finished.
+    pub fn annotator(&self) -> SigMfAnnotator {
+        SigMfAnnotator { annotations: self.annotations.clone() }
+    }
+
+    pub fn write_samples(&mut self, samples: &[Complex<f32>]) -> Result<(), Error> {
+        let bytes: Vec<u8> = samples.iter()
+            .flat_map(|s| [to_i8(s.re) as u8, to_i8(s.im) as u8])
+            .collect();
+        self.data.write_all(&bytes)
+    }
+
+    /// Finish the samples, then wait for every [`SigMfAnnotator`] to be dropped and write the
+    /// metadata with all their annotations.
+    pub fn finish(self) -> Result<(), Error> {
+        let SigMfWriter { mut data, annotations, meta } = self;
+        let flushed = data.flush();
+        drop(annotations);
+        let written = meta.join().unwrap_or_else(|_| Err(Error::other("SigMF metadata writer panicked")));
+        flushed.and(written)
+    }
+}
+
+impl SigMfAnnotator {
+    /// Mark where a packet was found.
+    pub fn annotate(&self, sample_start: u64, sample_count: u64, frequency: f64, packet: &DecodedPacket) {
+        let bandwidth = 40_000.0;
+        // the metadata writer only goes away early if writing failed, which finish reports
+        let _ = self.annotations.send(json!({
+            "core:sample_start": sample_start,
+            "core:sample_count": sample_count,
+            "core:freq_lower_edge": frequency - bandwidth / 2.0,
+            "core:freq_upper_edge": frequency + bandwidth / 2.0,
+            "core:label": format!("{} ptype {}", packet.serial(), packet.ptype()),
+            "telemega:serial": packet.serial(),
+            "telemega:ptype": packet.ptype(),
+        }));
+    }
+}
+
+struct Meta {
+    path: PathBuf,
+    sample_rate: f64,
+    center: f64,
+    hw: Option<String>,
+    datetime: String,
+    annotations: Vec<Value>,
+}
+
+impl Meta {
+    /// Collect annotations until every sender has gone, writing them out now and then on the way.
+    fn write_periodically(mut self, annotations: mpsc::Receiver<Value>) -> Result<(), Error> {
+        let mut written = Instant::now();
+        let mut changed = false;
+        loop {
+            match annotations.recv_timeout(META_INTERVAL.saturating_sub(written.elapsed())) {
+                Ok(annotation) => {
+                    self.annotations.push(annotation);
+                    changed = true;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return self.write(),
+            }
+            if changed && written.elapsed() >= META_INTERVAL {
+                self.write()?;
+                written = Instant::now();
+                changed = false;
+            }
+        }
+    }
+
+    fn write(&self) -> Result<(), Error> {
+        let mut global = json!({
+            "core:datatype": "ci8",
+            "core:sample_rate": self.sample_rate,
+            "core:version": "1.0.0",
+            "core:recorder": "telemega",
+            "core:extensions": [
+                { "name": "telemega", "version": "0.1.0", "optional": true }
+            ],
+        });
+        if let Some(hw) = &self.hw {
+            global["core:hw"] = json!(hw);
+        }
+        let meta = json!({
+            "global": global,
+            "captures": [{
+                "core:sample_start": 0,
+                "core:frequency": self.center,
+                "core:datetime": self.datetime,
+            }],
+            "annotations": self.annotations,
+        });
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        serde_json::to_writer_pretty(&mut file, &meta)?;
+        file.flush()
+    }
+}
+
+pub(crate) fn to_i8(x: f32) -> i8 {
+    x.round().clamp(i8::MIN as f32, i8::MAX as f32) as i8
+}
+
+/// Format a time as an ISO 8601 UTC timestamp, which is what SigMF uses for `core:datetime`.
+pub(crate) fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (hour, minute, second) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+
+    // days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:03}Z", since_epoch.subsec_millis())
+}
@@ -0,0 +1,89 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Error, Read};
+use std::path::Path;
+use num_complex::Complex;
+use crate::config::DEFAULT_SAMPLE_RATE;
+use crate::iq_source::read_samples;
+use crate::sample_format::SampleFormat;
+use crate::{wav, IQError, IQSource};
+
+/// Whether `path` should be read as a stream rather than a seekable recording: `-` for stdin, or
+/// anything that is not a regular file, such as a named pipe.
+pub(crate) fn is_stream(path: &Path) -> bool {
+    path == Path::new("-") || fs::metadata(path).is_ok_and(|meta| !meta.is_file())
+}
+
+/// Reads samples from a stream that cannot be seeked, such as stdin or a named pipe, so the
+/// decoder can sit at the end of a pipeline behind `hackrf_transfer`, `rx_sdr` or GNU Radio:
+///
+/// ```text
+/// hackrf_transfer -r - -f 436850000 -s 20000000 | tele-decode 436750000 --file -
+/// ```
+pub struct StreamIQSource {
+    reader: BufReader<Box<dyn Read + Send>>,
+    format: SampleFormat,
+    sample_rate: f64,
+    // bytes of a sample split across two reads
+    partial: Vec<u8>,
+}
+
+impl StreamIQSource {
+    pub fn new(reader: impl Read + Send + 'static, format: SampleFormat, sample_rate: f64) -> StreamIQSource {
+        StreamIQSource {
+            reader: BufReader::new(Box::new(reader)),
+            format,
+            sample_rate,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Start reading a stream, taking the format and sample rate from a WAV header if the stream
+    /// starts with one and they are not given.
+    ///
+    /// Like [`FileIQSource::open`](crate::FileIQSource::open), raw streams are otherwise assumed to
+    /// be `hackrf_transfer` output at [`DEFAULT_SAMPLE_RATE`].
+    pub fn from_reader(reader: impl Read + Send + 'static, format: Option<SampleFormat>, sample_rate: Option<f64>) -> Result<StreamIQSource, Error> {
+        let mut source = StreamIQSource::new(reader, format.unwrap_or(SampleFormat::Cs8), sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE));
+        if source.reader.fill_buf()?.starts_with(b"RIFF") {
+            let header = wav::read_header(&mut source.reader)?;
+            source.format = format.unwrap_or(header.format);
+            source.sample_rate = sample_rate.unwrap_or(header.sample_rate);
+        }
+        Ok(source)
+    }
+
+    /// Read from stdin.
+    pub fn stdin(format: Option<SampleFormat>, sample_rate: Option<f64>) -> Result<StreamIQSource, Error> {
+        StreamIQSource::from_reader(std::io::stdin(), format, sample_rate)
+    }
+
+    /// Read from a named pipe, guessing the format from its name like a recording.
+    pub fn open(path: impl AsRef<Path>, format: Option<SampleFormat>, sample_rate: Option<f64>) -> Result<StreamIQSource, Error> {
+        let path = path.as_ref();
+        // opening a pipe blocks until something opens the other end for writing
+        let file = File::open(path)?;
+        StreamIQSource::from_reader(file, format.or(SampleFormat::from_extension(path)), sample_rate)
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Throw away the next `samples` samples, the closest a stream gets to seeking.
+    pub fn skip(&mut self, samples: u64) -> Result<(), Error> {
+        let bytes = (samples * self.format.sample_size() as u64).saturating_sub(self.partial.len() as u64);
+        self.partial.clear();
+        std::io::copy(&mut self.reader.by_ref().take(bytes), &mut std::io::sink())?;
+        Ok(())
+    }
+}
+
+impl IQSource for StreamIQSource {
+    fn read(&mut self) -> Result<Vec<Complex<f32>>, IQError> {
+        read_samples(&mut self.reader, self.format, &mut self.partial)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+}
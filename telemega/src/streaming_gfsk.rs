@@ -1,48 +1,82 @@
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::f64::consts::PI;
 use num_complex::Complex;
 use ringbuffer::{AllocRingBuffer, RingBuffer};
 use crate::{ao, Packet};
-use crate::shifter::Shifter;
+use crate::filter::{butterworth_lowpass, sosfilt, Section};
+use crate::nco::Nco;
 
-const BUFFER_SIZE: usize = 1024 * 32;
-const OFFSET_SIZE: usize = 64;
-const SYNC_PATTERN: &[u8] = "1010101010101010101101001110010001".as_bytes();
+const BUFFER_SIZE: usize = 1024;
+pub(crate) const SYNC_PATTERN: &[u8] = "1010101010101010101101001110010001".as_bytes();
 const MESSAGE_BITS: usize = 72 * 8;
 const SYNC_BITS: usize = SYNC_PATTERN.len();
-const TOTAL_PACKET_BITS: usize = SYNC_BITS + MESSAGE_BITS;
-const AVERAGING_WIDTH: usize = 128;
+pub(crate) const TOTAL_PACKET_BITS: usize = SYNC_BITS + MESSAGE_BITS;
+/// Power of a full scale signed 8-bit sample, the reference for the reported RSSI.
+const FULL_SCALE_POWER: f32 = 128.0 * 128.0;
+/// Transmitters send more preamble ahead of the part the decoder looks for and carry on briefly
+/// after a packet, so samples this close to one are not counted as noise either.
+const NOISE_GUARD_BITS: f64 = 64.0;
+/// Channel filter, a 5th order Butterworth low-pass.
+const FILTER_ORDER: usize = 5;
+pub(crate) const FILTER_CUTOFF: f64 = 100_000.0 / 3.0;
+/// Delay the discriminator compares samples over, 64 samples at 20 MS/s.
+const OFFSET_SECONDS: f64 = 3.2e-6;
+/// Width of the moving average over the discriminator output, 128 samples at 20 MS/s.
+const AVERAGING_SECONDS: f64 = 6.4e-6;
+
 pub struct StreamingGFSKDecoder {
     buffer: Vec<Complex<f32>>,
 
     total_idx: usize,
 
-    previous_samples: [Complex<f32>;OFFSET_SIZE],
-    staging: [Complex<f32>;OFFSET_SIZE],
+    previous_samples: Vec<Complex<f32>>,
 
-    zi: [[Complex<f32>; 2]; 3],
+    sos: Vec<Section>,
+    zi: Vec<[Complex<f32>; 2]>,
 
-    shifter: Shifter,
+    nco: Nco,
 
     running_sum: f32,
-    avg_buff: [f32;AVERAGING_WIDTH],
+    avg_buff: Vec<f32>,
     avg_ring: AllocRingBuffer<f32>,
     bit_width: f64,
+
+    // filtered sample power and raw discriminator output, kept in step with avg_ring so that
+    // signal strength and carrier offset can be measured over exactly the packet's samples
+    power_ring: AllocRingBuffer<f32>,
+    disc_ring: AllocRingBuffer<Complex<f32>>,
+    // power of samples too old to be part of the packet in the ring, waiting to be sure they are
+    // not its preamble either, and how many more after a packet are still its tail
+    noise_ring: AllocRingBuffer<f32>,
+    noise_skip: usize,
+    noise_power: Option<f32>,
+    sample_rate: f64,
 }
 
 impl StreamingGFSKDecoder {
+    /// Decode the channel `center` Hz from the middle of samples at `sample_rate`, which should
+    /// already be narrowed down to the channel at around ten samples per bit by the channelizer.
     pub fn new(sample_rate: f64, center: f64, baud: f64) -> StreamingGFSKDecoder {
+        let offset = ((OFFSET_SECONDS * sample_rate).round() as usize).max(1);
+        let averaging_width = ((AVERAGING_SECONDS * sample_rate).round() as usize).max(1);
+        let ring_size = TOTAL_PACKET_BITS * (sample_rate/baud).ceil() as usize;
+        let sos = butterworth_lowpass(FILTER_ORDER, FILTER_CUTOFF, sample_rate);
         StreamingGFSKDecoder {
             buffer: Vec::with_capacity(BUFFER_SIZE),
             total_idx: 0,
-            previous_samples: [Complex::new(0.0, 0.0); OFFSET_SIZE],
-            staging: [Complex::new(0.0, 0.0); OFFSET_SIZE],
-            zi: [[Complex::new(0.0, 0.0); 2]; 3],
-            shifter: Shifter::new(-center, sample_rate),
-            avg_buff: [0f32;AVERAGING_WIDTH],
+            previous_samples: vec![Complex::new(0.0, 0.0); offset],
+            zi: vec![[Complex::new(0.0, 0.0); 2]; sos.len()],
+            sos,
+            nco: Nco::new(-center, sample_rate),
+            avg_buff: vec![0f32; averaging_width],
             bit_width: sample_rate/baud,
             running_sum: 0f32,
-            avg_ring: AllocRingBuffer::new(TOTAL_PACKET_BITS * (sample_rate/baud).ceil() as usize),
+            avg_ring: AllocRingBuffer::new(ring_size),
+            power_ring: AllocRingBuffer::new(ring_size),
+            disc_ring: AllocRingBuffer::new(ring_size),
+            noise_ring: AllocRingBuffer::new((NOISE_GUARD_BITS * sample_rate/baud).ceil() as usize),
+            noise_skip: 0,
+            noise_power: None,
+            sample_rate,
         }
     }
 
@@ -65,13 +99,42 @@ impl StreamingGFSKDecoder {
         }
     }
 
-    #[allow(unused)]
+
+    /// Decode whatever is left in the buffer once no more samples will be fed.
     pub fn finish(&mut self, for_each: impl FnMut(Packet)) {
-        if self.buffer.len() < OFFSET_SIZE {
+        if !self.buffer.is_empty() {
             self.process_buffer(for_each);
         }
     }
 
+    /// Samples were lost before the next ones to be fed. Decode what has been fed so far and
+    /// start afresh, so that a packet is not pieced together from either side of the gap.
+    pub fn restart(&mut self, mut for_each: impl FnMut(Packet)) {
+        if !self.buffer.is_empty() {
+            self.process_buffer(&mut for_each);
+        }
+        self.previous_samples.fill(Complex::new(0.0, 0.0));
+        self.zi.fill([Complex::new(0.0, 0.0); 2]);
+        self.avg_buff.fill(0.0);
+        self.running_sum = 0.0;
+        self.avg_ring.clear();
+        self.power_ring.clear();
+        self.disc_ring.clear();
+        self.noise_ring.clear();
+        self.noise_skip = 0;
+    }
+
+    /// Move the channel to `center` Hz from the middle of the samples, e.g. to follow a drifting
+    /// transmitter. Samples fed but not yet decoded are shifted by the new frequency too.
+    pub fn retune(&mut self, center: f64) {
+        self.nco.set_frequency(-center);
+    }
+
+    /// Index of the oldest sample in the ring, which is where a packet found by check_ring starts.
+    fn ring_start(&self, buffer_idx: usize) -> usize {
+        (self.total_idx + buffer_idx + 1).saturating_sub(self.avg_ring.len())
+    }
+
     fn check_ring(&mut self) -> Option<Packet> {
         if self.avg_ring.len() < (TOTAL_PACKET_BITS as f64 * self.bit_width) as usize {
             return None;
@@ -97,36 +160,80 @@ impl StreamingGFSKDecoder {
         let mut data = [0; 34];
         ao::fec_decode(&message, &mut data);
         let crc_match = data[data.len() - 1] == ao::FEC_DECODE_CRC_OK;
-        return Some(Packet{crc_match, data});
+        return Some(Packet::new(crc_match, data));
     }
 
-    fn process_buffer(&mut self, mut for_each: impl FnMut(Packet)) {
-        if self.buffer.len() < OFFSET_SIZE {
-            panic!("Error: Buffer too small.");
-        }
+    /// Fill in signal strength and frequency error of a packet occupying the whole ring.
+    fn measure(&self, packet: &mut Packet) {
+        let len = self.power_ring.len().max(1) as f32;
+        let power = self.power_ring.iter().sum::<f32>() / len;
+        packet.rssi = 10.0 * (power / FULL_SCALE_POWER).log10();
+        packet.snr = match self.noise_power {
+            Some(noise) if noise > 0.0 => 10.0 * (power / noise).log10(),
+            _ => f32::NAN,
+        };
 
-        self.shifter.shift(&mut self.buffer, self.total_idx);
-        sosfilt(&LOW_PASS_SOS_33K, &mut self.buffer, &mut self.zi);
-        self.staging.copy_from_slice(&self.buffer[self.buffer.len() - OFFSET_SIZE..]);
-        polar_discriminate(&mut self.buffer, &self.previous_samples);
+        // the discriminator compares each sample with the one `offset` earlier, so the phase
+        // of its average is the mean rotation over that delay; the two FSK tones cancel out and
+        // leave the carrier offset
+        let rotation = self.disc_ring.iter().sum::<Complex<f32>>().arg() as f64;
+        let offset = self.previous_samples.len() as f64;
+        packet.freq_error = (-rotation * self.sample_rate / (2.0 * PI * offset)) as f32;
+    }
+
+    fn process_buffer(&mut self, mut for_each: impl FnMut(Packet)) {
+        self.nco.shift(&mut self.buffer);
+        sosfilt(&self.sos, &mut self.buffer, &mut self.zi);
+        let powers: Vec<f32> = self.buffer.iter().map(|s| s.norm_sqr()).collect();
+        polar_discriminate(&mut self.buffer, &mut self.previous_samples);
+        let mut noise_sum = 0.0;
+        let mut noise_samples = 0;
 
         //periodically reset running sum to avoid floating point error buildup
         self.running_sum = self.avg_buff.iter().sum();
-        for i in 0..self.buffer.len() {
+        for (i, &power) in powers.iter().enumerate() {
             let s = self.buffer[i];
             let im = s.im;
             let idx = (self.total_idx + i) % self.avg_buff.len();
             self.running_sum = self.running_sum + im - self.avg_buff[idx];
             self.avg_buff[idx] = im;
-            self.avg_ring.push(self.running_sum / AVERAGING_WIDTH as f32);
-            if let Some(packet) = self.check_ring() {
+            self.avg_ring.push(self.running_sum / self.avg_buff.len() as f32);
+            // samples are only noise once they are too old to be part of a packet yet to be found
+            if let Some(&old) = self.power_ring.front().filter(|_| self.power_ring.is_full()) {
+                if self.noise_skip > 0 {
+                    self.noise_skip -= 1;
+                } else {
+                    if let Some(&noise) = self.noise_ring.front().filter(|_| self.noise_ring.is_full()) {
+                        noise_sum += noise;
+                        noise_samples += 1;
+                    }
+                    self.noise_ring.push(old);
+                }
+            }
+            self.power_ring.push(power);
+            self.disc_ring.push(s);
+            if let Some(mut packet) = self.check_ring() {
                 if packet.crc_match {
+                    packet.sample_idx = self.ring_start(i);
+                    self.measure(&mut packet);
                     for_each(packet);
+                    // nor do the packet's samples, or those around it
                     self.avg_ring.clear();
+                    self.power_ring.clear();
+                    self.disc_ring.clear();
+                    self.noise_ring.clear();
+                    self.noise_skip = self.noise_ring.capacity();
                 }
             }
         }
-        std::mem::swap(&mut self.previous_samples, &mut self.staging);
+
+        if noise_samples > 0 {
+            let power = noise_sum / noise_samples as f32;
+            self.noise_power = Some(match self.noise_power {
+                Some(noise) => 0.9 * noise + 0.1 * power,
+                None => power,
+            });
+        }
 
         self.total_idx += self.buffer.len();
         self.buffer.clear();
@@ -134,32 +241,127 @@ impl StreamingGFSKDecoder {
 }
 
 
-const LOW_PASS_SOS_33K: [[f32; 6]; 3] = [
-    [3.869494405731452e-12, 7.738988811462904e-12, 3.869494405731452e-12, 1.0, -0.989582475318754, 0.0],
-    [1.0, 2.0, 1.0, 1.0, -1.98308989599488, 0.9831986360344092],
-    [1.0, 1.0, 0.0, 1.0, -1.9934396492520414, 0.9935489568062257],
-];
+/// Replace each sample with the rotation from the one `prev.len()` before it, carrying the last
+/// of them over in `prev` for the next call.
+fn polar_discriminate(x: &mut [Complex<f32>], prev: &mut [Complex<f32>]) {
+    let offset = prev.len();
+    // the samples to compare the start of the next buffer with, taken before x is overwritten
+    let kept = offset.saturating_sub(x.len());
+    let mut next = prev[offset - kept..].to_vec();
+    next.extend_from_slice(&x[x.len() - (offset - kept)..]);
+
+    for i in (offset..x.len()).rev() {
+        x[i] = x[i-offset] * x[i].conj();
+    }
+    for i in 0..offset.min(x.len()) {
+        x[i] = prev[i] * x[i].conj();
+    }
+    prev.copy_from_slice(&next);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::{TelemetryModulator, TestFlight};
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 384_000.0;
+    const BAUD: f64 = 38_400.0;
 
+    /// Packets `offset` Hz from the center at `level` times the modulator's output, `gap` apart, in
+    /// noise.
+    fn flight(packets: usize, offset: f64, level: f32, gap: Duration) -> (Vec<Complex<f32>>, Vec<usize>) {
+        let mut modulator = TelemetryModulator::new(SAMPLE_RATE, BAUD, offset);
+        let mut flight = TestFlight::new(1234, "TEST");
+        let mut samples = modulator.silence(gap);
+        let mut starts = Vec::new();
+        for i in 0..packets {
+            starts.push(samples.len());
+            samples.extend(modulator.modulate(&flight.next_packet(i as f64)));
+            samples.extend(modulator.silence(gap));
+        }
+        // a fixed pseudo-random noise floor
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        for sample in samples.iter_mut() {
+            *sample = *sample * level + Complex::new(noise() * 8.0, noise() * 8.0);
+        }
+        (samples, starts)
+    }
 
-fn sosfilt<const N: usize>(sos: &[[f32; 6]; N], x: &mut [Complex<f32>], zi: &mut [[Complex<f32>; 2]; N]) {
-    for i in 0..x.len() {
-        let mut x_c = x[i];
-        for j in 0..N {
-            let section = &sos[j];
-            let x_n = section[0] * x_c + zi[j][0];
-            zi[j][0] = section[1] * x_c - section[4] * x_n + zi[j][1];
-            zi[j][1] = section[2] * x_c - section[5] * x_n;
-            x_c = x_n;
+    fn decode(decoder: &mut StreamingGFSKDecoder, samples: &[Complex<f32>]) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        for chunk in samples.chunks(1000) {
+            decoder.feed(chunk, |packet| packets.push(packet));
         }
-        x[i] = x_c;
+        decoder.finish(|packet| packets.push(packet));
+        packets
     }
-}
 
-fn polar_discriminate(x: &mut [Complex<f32>], prev: &[Complex<f32>;OFFSET_SIZE]) {
-    for i in (prev.len()..x.len()).rev() {
-        x[i] = x[i-prev.len()] * x[i].conj();
+    #[test]
+    fn decodes_packets() {
+        let (samples, starts) = flight(4, 0.0, 0.5, Duration::from_millis(20));
+        let packets = decode(&mut StreamingGFSKDecoder::new(SAMPLE_RATE, 0.0, BAUD), &samples);
+        assert_eq!(packets.len(), 4);
+        for (packet, start) in packets.iter().zip(starts) {
+            assert!(packet.crc_match);
+            // within the preamble
+            assert!(packet.sample_idx.abs_diff(start) < 40 * 10, "packet at {} sent at {start}", packet.sample_idx);
+            assert!(packet.freq_error.abs() < 2000.0, "frequency error {}", packet.freq_error);
+        }
     }
-    for i in 0..prev.len() {
-        x[i] = prev[i] * x[i].conj();
+
+    #[test]
+    fn decodes_off_center() {
+        let (samples, _) = flight(2, 25_000.0, 0.5, Duration::from_millis(20));
+        let packets = decode(&mut StreamingGFSKDecoder::new(SAMPLE_RATE, 25_000.0, BAUD), &samples);
+        assert_eq!(packets.len(), 2);
+    }
+
+    #[test]
+    fn retunes_between_packets() {
+        let (samples, starts) = flight(2, 25_000.0, 0.5, Duration::from_millis(20));
+        let mut decoder = StreamingGFSKDecoder::new(SAMPLE_RATE, 0.0, BAUD);
+        let mut packets = Vec::new();
+        decoder.feed(&samples[..starts[1]], |packet| packets.push(packet));
+        assert!(packets.is_empty());
+        decoder.retune(25_000.0);
+        decoder.feed(&samples[starts[1]..], |packet| packets.push(packet));
+        decoder.finish(|packet| packets.push(packet));
+        assert_eq!(packets.len(), 1);
+    }
+
+    #[test]
+    fn snr_is_measured_against_noise_only() {
+        // gaps shorter than a packet, so every buffer has some of a packet in it
+        let (samples, _) = flight(6, 0.0, 0.5, Duration::from_millis(5));
+        let packets = decode(&mut StreamingGFSKDecoder::new(SAMPLE_RATE, 0.0, BAUD), &samples);
+        assert_eq!(packets.len(), 6);
+
+        let (noise, _) = flight(0, 0.0, 0.0, Duration::from_millis(50));
+        let mut decoder = StreamingGFSKDecoder::new(SAMPLE_RATE, 0.0, BAUD);
+        decode(&mut decoder, &noise);
+        let noise = decoder.noise_power.unwrap();
+        let signal = 10.0 * (10f32.powf(packets[0].rssi / 10.0) * FULL_SCALE_POWER / noise).log10();
+        for packet in &packets[1..] {
+            assert!((packet.snr - signal).abs() < 1.0, "SNR {} expected {signal}", packet.snr);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn restart_forgets_partial_packet() {
+        let (samples, starts) = flight(2, 0.0, 0.5, Duration::from_millis(20));
+        let mut decoder = StreamingGFSKDecoder::new(SAMPLE_RATE, 0.0, BAUD);
+        let mut packets = Vec::new();
+        // lose the middle of the first packet
+        let cut = starts[0] + 2000;
+        decoder.feed(&samples[..cut], |packet| packets.push(packet));
+        decoder.restart(|packet| packets.push(packet));
+        decoder.feed(&samples[cut + 1000..], |packet| packets.push(packet));
+        decoder.finish(|packet| packets.push(packet));
+        assert_eq!(packets.len(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+//! Sweeping a HackRF across a band to find flight computers that are transmitting.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use async_libhackrf::{HackRfOne, RxConfig, SweepBlock, SweepConfig, SweepMode, SweepStyle};
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use crate::gain::GainSettings;
+use crate::iq_source::{open_radio, IQError};
+
+/// Sweeps at the HackRF's highest sample rate, as `hackrf_sweep` does.
+const SWEEP_SAMPLE_RATE: f64 = 20_000_000.0;
+const SWEEP_FILTER_BANDWIDTH: u32 = 15_000_000;
+/// Each tuning is used from 7.5 MHz below to 7.5 MHz above, without the DC spike in the middle.
+const SWEEP_OFFSET: f64 = 7_500_000.0;
+const SWEEP_STEP: f64 = SWEEP_SAMPLE_RATE;
+/// About 10 kHz per bin, narrow enough to pick out a 38400 baud AltOS signal.
+const FFT_SIZE: usize = 2048;
+const FULL_SCALE_POWER: f32 = 128.0 * 128.0;
+
+/// Occupied bandwidth of AltOS telemetry is around 80 kHz, allow for the sweep's coarse bins.
+const MIN_TELEMETRY_BANDWIDTH: f64 = 30_000.0;
+const MAX_TELEMETRY_BANDWIDTH: f64 = 200_000.0;
+/// Telemetry is sent in short bursts, so its average over a scan is well below its peak,
+/// unlike a carrier that is on all the time.
+const MIN_BURSTINESS_DB: f32 = 6.0;
+
+/// Power across a range of frequencies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrum {
+    /// Center frequency of the first bin in Hz.
+    pub start: f64,
+    /// Width of each bin in Hz.
+    pub bin_width: f64,
+    /// Power of each bin in dB relative to a full scale 8-bit sample.
+    pub power: Vec<f32>,
+}
+
+impl Spectrum {
+    fn new(start: f64, stop: f64, bin_width: f64) -> Spectrum {
+        let bins = ((stop - start) / bin_width).round() as usize;
+        Spectrum { start, bin_width, power: vec![f32::NEG_INFINITY; bins] }
+    }
+
+    /// Center frequency of `bin` in Hz.
+    pub fn frequency(&self, bin: usize) -> f64 {
+        self.start + bin as f64 * self.bin_width
+    }
+
+    fn bin(&self, frequency: f64) -> Option<usize> {
+        let bin = ((frequency - self.start) / self.bin_width).round();
+        (bin >= 0.0 && (bin as usize) < self.power.len()).then_some(bin as usize)
+    }
+
+    /// Median power, which is the noise floor in a band that is mostly quiet.
+    pub fn noise_floor(&self) -> f32 {
+        let mut sorted: Vec<f32> = self.power.iter().copied().filter(|p| p.is_finite()).collect();
+        sorted.sort_by(f32::total_cmp);
+        sorted.get(sorted.len() / 2).copied().unwrap_or(f32::NEG_INFINITY)
+    }
+}
+
+/// A signal found by [`BandScan::carriers`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Carrier {
+    /// Center of the signal in Hz.
+    pub frequency: f64,
+    /// Width of the signal in Hz.
+    pub bandwidth: f64,
+    /// Strongest power seen in dB relative to a full scale 8-bit sample.
+    pub peak: f32,
+    /// Strongest power relative to the noise floor in dB.
+    pub snr: f32,
+    /// Whether it looks like AltOS telemetry: about the right width and only on some of the time.
+    pub telemetry: bool,
+}
+
+/// The peak and average of many sweeps across a band, so that short telemetry bursts are caught.
+#[derive(Clone, Debug)]
+pub struct BandScan {
+    peak: Spectrum,
+    // linear power summed over the sweeps
+    total: Vec<f64>,
+    sweeps: usize,
+}
+
+impl BandScan {
+    pub fn new() -> BandScan {
+        BandScan {
+            peak: Spectrum { start: 0.0, bin_width: 0.0, power: Vec::new() },
+            total: Vec::new(),
+            sweeps: 0,
+        }
+    }
+
+    /// Number of sweeps added so far.
+    pub fn sweeps(&self) -> usize {
+        self.sweeps
+    }
+
+    pub fn add(&mut self, spectrum: &Spectrum) {
+        if self.sweeps == 0 {
+            self.peak = spectrum.clone();
+            self.total = vec![0.0; spectrum.power.len()];
+        }
+        for ((peak, total), power) in self.peak.power.iter_mut().zip(&mut self.total).zip(&spectrum.power) {
+            *peak = peak.max(*power);
+            *total += 10f64.powf(*power as f64 / 10.0);
+        }
+        self.sweeps += 1;
+    }
+
+    /// Strongest power seen in each bin.
+    pub fn peak(&self) -> &Spectrum {
+        &self.peak
+    }
+
+    /// Average power in each bin.
+    pub fn average(&self) -> Spectrum {
+        let power = self.total.iter()
+            .map(|total| (10.0 * (total / self.sweeps.max(1) as f64).log10()) as f32)
+            .collect();
+        Spectrum { power, ..self.peak.clone() }
+    }
+
+    /// Runs of bins that peaked at least `threshold_db` above the noise floor, strongest first.
+    pub fn carriers(&self, threshold_db: f32) -> Vec<Carrier> {
+        let peak = &self.peak;
+        let average = self.average();
+        let floor = average.noise_floor();
+        let mut carriers = Vec::new();
+        let mut bin = 0;
+        while bin < peak.power.len() {
+            if peak.power[bin] < floor + threshold_db {
+                bin += 1;
+                continue;
+            }
+            let start = bin;
+            while bin < peak.power.len() && peak.power[bin] >= floor + threshold_db {
+                bin += 1;
+            }
+            let bins = start..bin;
+            let strongest = bins.clone().max_by(|a, b| peak.power[*a].total_cmp(&peak.power[*b])).unwrap();
+            let bandwidth = bins.len() as f64 * peak.bin_width;
+            let burstiness = peak.power[strongest] - average.power[strongest];
+            carriers.push(Carrier {
+                frequency: (peak.frequency(start) + peak.frequency(bin - 1)) / 2.0,
+                bandwidth,
+                peak: peak.power[strongest],
+                snr: peak.power[strongest] - floor,
+                telemetry: (MIN_TELEMETRY_BANDWIDTH..=MAX_TELEMETRY_BANDWIDTH).contains(&bandwidth)
+                    && burstiness >= MIN_BURSTINESS_DB,
+            });
+        }
+        carriers.sort_by(|a, b| b.peak.total_cmp(&a.peak));
+        carriers
+    }
+}
+
+impl Default for BandScan {
+    fn default() -> BandScan {
+        BandScan::new()
+    }
+}
+
+/// A HackRF in sweep mode, measuring the spectrum from `start` to `stop` over and over.
+pub struct HackRFSweeper {
+    hack_rf: HackRfOne<SweepMode>,
+    start: f64,
+    stop: f64,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    current: Spectrum,
+    // blocks received but not yet added to a spectrum
+    blocks: VecDeque<SweepBlock>,
+}
+
+impl HackRFSweeper {
+    /// Open the HackRF with the given serial number, or the first one not in use without one,
+    /// and start sweeping from `start` to at least `stop` Hz.
+    pub fn open(serial: Option<&str>, start: f64, stop: f64, gain: GainSettings) -> Result<HackRFSweeper, IQError> {
+        let mut hack_rf = open_radio(serial)?;
+        hack_rf.set_sample_rate(SWEEP_SAMPLE_RATE as u32, 1)?;
+        hack_rf.set_baseband_filter_bandwidth(SWEEP_FILTER_BANDWIDTH)?;
+        hack_rf.set_amp_enable(gain.amp)?;
+        hack_rf.set_lna_gain(gain.lna)?;
+        hack_rf.set_vga_gain(gain.vga)?;
+
+        // the radio only tunes to whole MHz and whole steps
+        let start_mhz = (start / 1e6).floor();
+        let steps = ((stop / 1e6 - start_mhz) / (SWEEP_STEP / 1e6)).ceil().max(1.0);
+        let stop_mhz = start_mhz + steps * SWEEP_STEP / 1e6;
+        let config = SweepConfig {
+            ranges: vec![(start_mhz as u16, stop_mhz as u16)],
+            step_width: SWEEP_STEP as u32,
+            offset: SWEEP_OFFSET as u32,
+            style: SweepStyle::Interleaved,
+        };
+        let hack_rf = hack_rf.into_sweep_mode(&config, RxConfig::default())?;
+
+        let bin_width = SWEEP_SAMPLE_RATE / FFT_SIZE as f64;
+        let (start, stop) = (start_mhz * 1e6, stop_mhz * 1e6);
+        Ok(HackRFSweeper {
+            hack_rf,
+            start,
+            stop,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window: (0..FFT_SIZE)
+                .map(|i| (std::f32::consts::PI * i as f32 / FFT_SIZE as f32).sin().powi(2))
+                .collect(),
+            current: Spectrum::new(start, stop, bin_width),
+            blocks: VecDeque::new(),
+        })
+    }
+
+    /// Frequency range actually swept, rounded out to what the radio can do.
+    pub fn range(&self) -> (f64, f64) {
+        (self.start, self.stop)
+    }
+
+    /// Wait for the next complete sweep.
+    pub fn sweep(&mut self) -> Result<Spectrum, IQError> {
+        let mut filled = false;
+        loop {
+            let Some(block) = self.blocks.pop_front() else {
+                self.blocks.extend(self.hack_rf.sweep_rx()?);
+                continue;
+            };
+            // back at the start means the last sweep is complete
+            if block.frequency as f64 == self.start && filled {
+                self.blocks.push_front(block);
+                let empty = Spectrum::new(self.start, self.stop, self.current.bin_width);
+                return Ok(std::mem::replace(&mut self.current, empty));
+            }
+            self.add_block(&block);
+            filled = true;
+        }
+    }
+
+    fn add_block(&mut self, block: &SweepBlock) {
+        // the end of the block, after the radio has settled on the new frequency
+        let samples = &block.samples[block.samples.len() - FFT_SIZE * 2..];
+        let mut buffer: Vec<Complex<f32>> = samples.chunks_exact(2)
+            .zip(&self.window)
+            .map(|(iq, w)| Complex::new((iq[0] as i8) as f32 * w, (iq[1] as i8) as f32 * w))
+            .collect();
+        self.fft.process(&mut buffer);
+        let scale = (FFT_SIZE * FFT_SIZE) as f32 * FULL_SCALE_POWER;
+        let power: Vec<f32> = buffer.iter().map(|x| 10.0 * (x.norm_sqr() / scale).log10()).collect();
+
+        // the radio is tuned SWEEP_OFFSET above the block's frequency; keep the quarter of the
+        // band from there up to 5 MHz above it and the quarter from 10 to 15 MHz above it, which
+        // start 3/8 of the way below and 1/8 of the way above the tuned frequency
+        let quarter = FFT_SIZE / 4;
+        let frequency = block.frequency as f64;
+        let halves = [
+            (frequency, 1 + FFT_SIZE * 5 / 8),
+            (frequency + SWEEP_SAMPLE_RATE / 2.0, 1 + FFT_SIZE / 8),
+        ];
+        for (start, first_bin) in halves {
+            for i in 0..quarter {
+                let bin_frequency = start + (i + 1) as f64 * self.current.bin_width;
+                if let Some(bin) = self.current.bin(bin_frequency) {
+                    self.current.power[bin] = power[(first_bin + i) % FFT_SIZE];
+                }
+            }
+        }
+    }
+
+    /// Take the radio out of sweep mode.
+    pub fn stop(self) -> Result<(), IQError> {
+        self.hack_rf.stop_sweep()?;
+        Ok(())
+    }
+}
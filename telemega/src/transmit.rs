@@ -0,0 +1,213 @@
+//! Transmitting synthetic AltOS telemetry with a HackRF, to check the whole receive chain on a bench
+//! before a launch.
+
+use std::time::Duration;
+use async_libhackrf::{HackRfOne, RxConfig, TxMode};
+use num_complex::Complex;
+use crate::ao;
+use crate::iq_source::{open_radio, sample_rate_params, IQError};
+use crate::sigmf::to_i8;
+use crate::streaming_gfsk::SYNC_PATTERN;
+
+/// Frequency deviation of AltOS telemetry at 38400 baud.
+const DEVIATION: f64 = 20_500.0;
+/// Extra preamble ahead of the part the decoder looks for, so that its filters have settled.
+const EXTRA_PREAMBLE_BITS: usize = 32;
+/// Bits sent after the message so that the last one is not cut short by the filters.
+const TAIL_BITS: usize = 8;
+const AMPLITUDE: f32 = 120.0;
+/// Height in meters the pretend flight deploys its main at.
+const MAIN_DEPLOY: f64 = 250.0;
+
+/// Turns AltOS packets into GFSK samples, as a TeleMega's radio would send them.
+pub struct TelemetryModulator {
+    sample_rate: f64,
+    baud: f64,
+    offset: f64,
+    phase: f64,
+}
+
+impl TelemetryModulator {
+    /// Modulate packets `offset` Hz from the center of samples at `sample_rate`.
+    pub fn new(sample_rate: f64, baud: f64, offset: f64) -> TelemetryModulator {
+        TelemetryModulator { sample_rate, baud, offset, phase: 0.0 }
+    }
+
+    /// Samples of one packet: preamble, sync word and the FEC encoded `payload` with its CRC.
+    pub fn modulate(&mut self, payload: &[u8; 32]) -> Vec<Complex<f32>> {
+        let mut bits: Vec<bool> = (0..EXTRA_PREAMBLE_BITS).map(|i| i % 2 == 0).collect();
+        bits.extend(SYNC_PATTERN.iter().map(|&bit| bit == b'1'));
+        for byte in ao::fec_encode(payload) {
+            bits.extend((0..8).rev().map(|i| byte & (1 << i) != 0));
+        }
+        bits.extend((0..TAIL_BITS).map(|i| i % 2 == 0));
+
+        let bit_width = self.sample_rate / self.baud;
+        let len = (bits.len() as f64 * bit_width) as usize;
+        let mut frequency: Vec<f64> = (0..len)
+            .map(|i| if bits[(i as f64 / bit_width) as usize] { DEVIATION } else { -DEVIATION })
+            .collect();
+        // two half bit moving averages come close enough to the Gaussian filter of a real radio
+        let width = (bit_width / 2.0).max(1.0) as usize;
+        moving_average(&mut frequency, width);
+        moving_average(&mut frequency, width);
+
+        frequency.into_iter()
+            .map(|deviation| {
+                self.phase = (self.phase + 2.0 * std::f64::consts::PI * (self.offset + deviation) / self.sample_rate)
+                    % (2.0 * std::f64::consts::PI);
+                Complex::from_polar(AMPLITUDE, self.phase as f32)
+            })
+            .collect()
+    }
+
+    /// Nothing transmitted for `duration`.
+    pub fn silence(&self, duration: Duration) -> Vec<Complex<f32>> {
+        vec![Complex::new(0.0, 0.0); (duration.as_secs_f64() * self.sample_rate) as usize]
+    }
+}
+
+fn moving_average(x: &mut [f64], width: usize) {
+    let mut history = vec![x.first().copied().unwrap_or_default(); width];
+    let mut sum: f64 = history.iter().sum();
+    for (i, value) in x.iter_mut().enumerate() {
+        sum += *value - history[i % width];
+        history[i % width] = *value;
+        *value = sum / width as f64;
+    }
+}
+
+/// Packets from a pretend flight: a boost, coast to apogee and descent under a main, with a
+/// config packet every so often as a real flight computer sends.
+pub struct TestFlight {
+    serial: u16,
+    callsign: String,
+    sent: u32,
+}
+
+impl TestFlight {
+    pub fn new(serial: u16, callsign: impl Into<String>) -> TestFlight {
+        TestFlight { serial, callsign: callsign.into(), sent: 0 }
+    }
+
+    /// The next packet to send at `time` seconds into the flight.
+    pub fn next_packet(&mut self, time: f64) -> [u8; 32] {
+        let tick = ((time * 100.0) as u64 % 65536) as u16;
+        self.sent += 1;
+        let mut packet = [0u8; 32];
+        packet[0..2].copy_from_slice(&self.serial.to_le_bytes());
+        packet[2..4].copy_from_slice(&tick.to_le_bytes());
+
+        if self.sent % 5 == 1 {
+            packet[4] = 4;
+            // flight 1, config version 2.0, no apogee delay, main at MAIN_DEPLOY
+            packet[6..8].copy_from_slice(&1u16.to_le_bytes());
+            packet[8] = 2;
+            packet[10..12].copy_from_slice(&0u16.to_le_bytes());
+            packet[12..14].copy_from_slice(&(MAIN_DEPLOY as u16).to_le_bytes());
+            packet[14..16].copy_from_slice(&4096u16.to_le_bytes());
+            write_padded(&mut packet[16..24], &self.callsign);
+            write_padded(&mut packet[24..32], "test");
+            return packet;
+        }
+
+        let (state, height, speed, acceleration) = flight_profile(time);
+        packet[4] = 9;
+        packet[5] = state;
+        packet[6..8].copy_from_slice(&3900i16.to_le_bytes());
+        packet[8..10].copy_from_slice(&3900i16.to_le_bytes());
+        // ground pressure, ground and calibration accelerometer readings are left at zero
+        packet[26..28].copy_from_slice(&((acceleration * 16.0) as i16).to_le_bytes());
+        packet[28..30].copy_from_slice(&((speed * 16.0) as i16).to_le_bytes());
+        packet[30..32].copy_from_slice(&(height as i16).to_le_bytes());
+        packet
+    }
+}
+
+fn write_padded(out: &mut [u8], text: &str) {
+    for (out, byte) in out.iter_mut().zip(text.bytes().chain(std::iter::repeat(0))) {
+        *out = byte;
+    }
+}
+
+/// AltOS state, height (m), speed (m/s) and acceleration (m/s²) `time` seconds after launch.
+fn flight_profile(time: f64) -> (u8, f64, f64, f64) {
+    const BURN: f64 = 3.0;
+    const THRUST: f64 = 100.0;
+    const G: f64 = 9.8;
+    const DESCENT: f64 = -20.0;
+
+    let burnout_speed = THRUST * BURN;
+    let burnout_height = THRUST * BURN * BURN / 2.0;
+    let apogee_time = BURN + burnout_speed / G;
+    let apogee = burnout_height + burnout_speed * burnout_speed / (2.0 * G);
+
+    if time < BURN {
+        (3, THRUST * time * time / 2.0, THRUST * time, THRUST)
+    } else if time < apogee_time {
+        let coast = time - BURN;
+        (5, burnout_height + burnout_speed * coast - G * coast * coast / 2.0, burnout_speed - G * coast, -G)
+    } else {
+        let height = apogee + DESCENT * (time - apogee_time);
+        if height > MAIN_DEPLOY {
+            (6, height, DESCENT, 0.0)
+        } else if height > 0.0 {
+            (7, height, DESCENT, 0.0)
+        } else {
+            (8, 0.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// A HackRF transmitting at `center` Hz.
+pub struct HackRFTransmitter {
+    hack_rf: HackRfOne<TxMode>,
+    sample_rate: f64,
+}
+
+impl HackRFTransmitter {
+    /// Open the HackRF with the given serial number, or the first one not in use without one.
+    ///
+    /// `gain` is the TX VGA gain in dB (0 to 47). Keep it and the amplifier down and transmit
+    /// into a dummy load or attenuator.
+    pub fn open(serial: Option<&str>, center: f64, sample_rate: f64, gain: u16, amp: bool) -> Result<HackRFTransmitter, IQError> {
+        let mut hack_rf = open_radio(serial)?;
+
+        let (freq_hz, divider) = sample_rate_params(sample_rate);
+        hack_rf.set_sample_rate(freq_hz, divider)?;
+        hack_rf.set_freq(center as u64)?;
+        hack_rf.set_amp_enable(amp)?;
+        hack_rf.set_txvga_gain(gain)?;
+        let hack_rf = hack_rf.into_tx_mode(RxConfig::default())?;
+        Ok(HackRFTransmitter { hack_rf, sample_rate })
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Queue samples for transmission, blocking while the radio catches up.
+    pub fn send(&mut self, samples: &[Complex<f32>]) -> Result<(), IQError> {
+        let bytes: Vec<u8> = samples.iter()
+            .flat_map(|s| [to_i8(s.re) as u8, to_i8(s.im) as u8])
+            .collect();
+        Ok(self.hack_rf.tx(&bytes)?)
+    }
+
+    /// Samples sent in each USB transfer, so the most worth passing to [`send`](Self::send) or
+    /// [`send_silence`](Self::send_silence) at once.
+    pub fn transfer_samples(&self) -> usize {
+        RxConfig::default().transfer_size / 2
+    }
+
+    /// Queue `samples` samples of nothing, blocking while the radio catches up.
+    pub fn send_silence(&mut self, samples: usize) -> Result<(), IQError> {
+        Ok(self.hack_rf.tx(&vec![0; samples * 2])?)
+    }
+
+    /// Transmit what is still queued and switch the transmitter off.
+    pub fn stop(self) -> Result<(), IQError> {
+        self.hack_rf.stop_tx()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::{Error, ErrorKind, Read};
+use byteorder::{LittleEndian, ReadBytesExt};
+use crate::sample_format::SampleFormat;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// What is needed from a stereo (I/Q) WAV recording header.
+pub(crate) struct WavHeader {
+    pub format: SampleFormat,
+    pub sample_rate: f64,
+    /// Bytes of sample data, after which there may be other chunks such as LIST or id3 tags. None
+    /// if the recorder did not know the length when it wrote the header.
+    pub data_len: Option<u64>,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Parse a RIFF/WAVE header, leaving `reader` at the first byte of the sample data.
+pub(crate) fn read_header(reader: &mut impl Read) -> Result<WavHeader, Error> {
+    let mut tag = [0u8; 4];
+    reader.read_exact(&mut tag)?;
+    if &tag != b"RIFF" {
+        return Err(invalid("not a RIFF file"));
+    }
+    reader.read_u32::<LittleEndian>()?;
+    reader.read_exact(&mut tag)?;
+    if &tag != b"WAVE" {
+        return Err(invalid("not a WAVE file"));
+    }
+
+    let mut fmt: Option<WavHeader> = None;
+    loop {
+        reader.read_exact(&mut tag)?;
+        let len = reader.read_u32::<LittleEndian>()? as u64;
+        match &tag {
+            b"fmt " => {
+                let mut audio_format = reader.read_u16::<LittleEndian>()?;
+                let channels = reader.read_u16::<LittleEndian>()?;
+                let sample_rate = reader.read_u32::<LittleEndian>()?;
+                reader.read_u32::<LittleEndian>()?; // byte rate
+                reader.read_u16::<LittleEndian>()?; // block align
+                let bits = reader.read_u16::<LittleEndian>()?;
+                let mut read = 16;
+                if audio_format == WAVE_FORMAT_EXTENSIBLE && len >= 40 {
+                    reader.read_u16::<LittleEndian>()?; // extension size
+                    reader.read_u16::<LittleEndian>()?; // valid bits
+                    reader.read_u32::<LittleEndian>()?; // channel mask
+                    // the sub format GUID starts with the plain format code
+                    audio_format = reader.read_u16::<LittleEndian>()?;
+                    read = 26;
+                }
+                std::io::copy(&mut reader.by_ref().take(len.saturating_sub(read) + len % 2), &mut std::io::sink())?;
+
+                if channels != 2 {
+                    return Err(invalid("IQ WAV files must have exactly two channels"));
+                }
+                let format = match (audio_format, bits) {
+                    (WAVE_FORMAT_PCM, 8) => SampleFormat::Cu8,
+                    (WAVE_FORMAT_PCM, 16) => SampleFormat::Cs16,
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Cf32,
+                    _ => return Err(invalid("unsupported WAV sample format")),
+                };
+                fmt = Some(WavHeader { format, sample_rate: sample_rate as f64, data_len: None });
+            }
+            b"data" => {
+                let mut header = fmt.ok_or_else(|| invalid("WAV data chunk before fmt chunk"))?;
+                // streaming recorders leave the length at 0 or all ones
+                header.data_len = (len != 0 && len != u32::MAX as u64).then_some(len);
+                return Ok(header);
+            }
+            _ => {
+                // chunks are padded to an even length
+                std::io::copy(&mut reader.by_ref().take(len + len % 2), &mut std::io::sink())?;
+            }
+        }
+    }
+}